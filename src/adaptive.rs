@@ -0,0 +1,77 @@
+// Adaptive sampling: every pixel gets a minimum number of samples, after which only the pixels
+// whose estimate is still noisy keep being sampled, in batches, up to the samples per pixel of the
+// render. Flat areas like an empty background stop early and leave the time to the noisy ones.
+use super::checkpoint;
+use super::color;
+use super::utils::RayTracingFloat;
+
+#[derive(Clone)]
+pub struct AdaptiveSettings {
+    pub min_samples: usize, // taken by every pixel, and between error checks
+    pub threshold: RayTracingFloat, // standard error at which a pixel is done
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        return Self {
+            min_samples: 32,
+            threshold: 0.005,
+        };
+    }
+}
+
+//
+// Error Estimate
+//
+// Running mean and variance (Welford's algorithm) of the luminance of a pixel's samples. The
+// luminance is compressed, like a tone mapper would, so that the rare very bright sample of a
+// light does not keep a pixel going forever and noise in dark areas still counts.
+#[derive(Clone)]
+pub struct ErrorEstimate {
+    samples: usize,
+    mean: RayTracingFloat,
+    m2: RayTracingFloat, // sum of squared differences from the mean
+}
+
+impl ErrorEstimate {
+    pub fn new() -> Self {
+        return Self {
+            samples: 0,
+            mean: 0.0,
+            m2: 0.0,
+        };
+    }
+
+    pub fn add(&mut self, c: &color::Color) {
+        let luminance = 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        let value = luminance / (1.0 + luminance.abs());
+
+        self.samples += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.samples as RayTracingFloat;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Standard error of the pixel's mean.
+    pub fn error(&self) -> RayTracingFloat {
+        if self.samples < 2 {
+            return RayTracingFloat::INFINITY;
+        }
+        let n = self.samples as RayTracingFloat;
+        let variance = self.m2 / (n - 1.0);
+        return (variance / n).sqrt();
+    }
+
+    pub fn save(&self, w: &mut checkpoint::Writer) {
+        w.u64(&(self.samples as u64));
+        w.f64(&self.mean);
+        w.f64(&self.m2);
+    }
+
+    pub fn load(&mut self, r: &mut checkpoint::Reader) -> Result<(), String> {
+        self.samples = r.u64()? as usize;
+        self.mean = r.f64()?;
+        self.m2 = r.f64()?;
+        return Ok(());
+    }
+}
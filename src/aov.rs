@@ -0,0 +1,288 @@
+// Arbitrary output variables: auxiliary passes describing the surfaces seen through each pixel,
+// written next to the beauty image for denoising and compositing.
+use super::checkpoint;
+use super::color;
+use super::hittable;
+use super::ray;
+use super::utils::RayTracingFloat;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Aov {
+    Depth,    // distance from the camera to the first hit
+    Normal,   // world space normal at the first hit, facing the camera
+    Albedo,   // surface color at the first hit
+    Position, // world space position of the first hit
+    Uv,       // surface coordinates of the first hit
+    Samples,  // number of samples taken for the pixel
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::Uv,
+        Aov::Samples,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::Samples => "samples",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return Self::ALL.iter().find(|aov| aov.name() == name).copied();
+    }
+
+    // Channel names within the pass, e.g. for the "layer.channel" names of EXR files.
+    pub fn channels(&self) -> &'static [&'static str] {
+        return match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Uv => &["U", "V"],
+            Aov::Samples => &["Y"],
+        };
+    }
+}
+
+// Parses a comma separated list of passes, or "all".
+pub fn parse_list(list: &str) -> Result<Vec<Aov>, String> {
+    if list == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    let mut aovs = Vec::new();
+    for name in list.split(',') {
+        let aov = Aov::from_name(name.trim())
+            .ok_or_else(|| format!("unknown output variable '{}'", name))?;
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+    return Ok(aovs);
+}
+
+//
+// AOV Sample
+//
+// What a single camera ray sees first.
+pub struct AovSample {
+    pub hit: bool,
+    pub depth: RayTracingFloat,
+    pub normal: ray::Vector,
+    pub albedo: color::Color,
+    pub position: ray::Point,
+    pub u: RayTracingFloat,
+    pub v: RayTracingFloat,
+}
+
+impl AovSample {
+    pub fn new() -> Self {
+        return Self {
+            hit: false,
+            depth: 0.0,
+            normal: ray::Vector::zero(),
+            albedo: color::Color::zero(),
+            position: ray::Point::zero(),
+            u: 0.0,
+            v: 0.0,
+        };
+    }
+
+    pub fn record_hit(&mut self, r: &ray::Ray, rec: &hittable::HitRecord) {
+        self.hit = true;
+        // Camera rays are not normalized, so t is not a distance by itself.
+        self.depth = rec.t * r.direction().length();
+        self.normal = rec.normal().clone();
+        self.albedo = rec.material().albedo(rec);
+        self.position = rec.point().clone();
+        self.u = rec.u;
+        self.v = rec.v;
+    }
+
+    pub fn record_miss(&mut self, background: &color::Color) {
+        self.hit = false;
+        // Denoisers expect the albedo of the background to be its color.
+        self.albedo = background.clone();
+    }
+}
+
+//
+// AOV Pixel
+//
+// Accumulates the samples of a pixel. Geometric passes are averaged over the samples which hit
+// something, so that e.g. the depth at an object's silhouette is not pulled towards zero.
+#[derive(Clone)]
+pub struct AovPixel {
+    samples: usize,
+    hits: usize,
+    depth: RayTracingFloat,
+    normal: ray::Vector,
+    albedo: color::Color,
+    position: ray::Point,
+    uv: ray::Vector,
+}
+
+impl AovPixel {
+    pub fn new() -> Self {
+        return Self {
+            samples: 0,
+            hits: 0,
+            depth: 0.0,
+            normal: ray::Vector::zero(),
+            albedo: color::Color::zero(),
+            position: ray::Point::zero(),
+            uv: ray::Vector::zero(),
+        };
+    }
+
+    pub fn add(&mut self, sample: &AovSample) {
+        self.samples += 1;
+        self.albedo += &sample.albedo;
+        if sample.hit {
+            self.hits += 1;
+            self.depth += sample.depth;
+            self.normal += &sample.normal;
+            self.position += &sample.position;
+            self.uv += ray::Vector::new(sample.u, sample.v, 0.0);
+        }
+    }
+
+    pub fn save(&self, w: &mut checkpoint::Writer) {
+        w.u64(&(self.samples as u64));
+        w.u64(&(self.hits as u64));
+        w.f64(&self.depth);
+        w.color(&self.normal);
+        w.color(&self.albedo);
+        w.color(&self.position);
+        w.color(&self.uv);
+    }
+
+    pub fn load(&mut self, r: &mut checkpoint::Reader) -> Result<(), String> {
+        self.samples = r.u64()? as usize;
+        self.hits = r.u64()? as usize;
+        self.depth = r.f64()?;
+        self.normal = r.color()?;
+        self.albedo = r.color()?;
+        self.position = r.color()?;
+        self.uv = r.color()?;
+        return Ok(());
+    }
+
+    // The value of a pass. Single channel passes are repeated in all three components, so that
+    // they show up as gray in color images.
+    pub fn value(&self, aov: &Aov) -> color::Color {
+        if *aov == Aov::Albedo {
+            return &self.albedo / self.samples.max(1) as RayTracingFloat;
+        }
+        if *aov == Aov::Samples {
+            let samples = self.samples as RayTracingFloat;
+            return color::Color::new(samples, samples, samples);
+        }
+        if self.hits == 0 {
+            return color::Color::zero();
+        }
+
+        let hits = self.hits as RayTracingFloat;
+        return match aov {
+            Aov::Depth => {
+                let depth = self.depth / hits;
+                color::Color::new(depth, depth, depth)
+            }
+            Aov::Normal => {
+                if self.normal.near_zero() {
+                    color::Color::zero()
+                } else {
+                    self.normal.unit_vector()
+                }
+            }
+            Aov::Position => &self.position / hits,
+            Aov::Uv => &self.uv / hits,
+            Aov::Albedo | Aov::Samples => unreachable!(),
+        };
+    }
+}
+
+//
+// Pass
+//
+// One output variable over the whole image, stored row by row from the top.
+pub struct Pass {
+    pub aov: Aov,
+    pub pixels: Vec<color::Color>,
+}
+
+impl Pass {
+    pub fn new(aov: &Aov, pixels: &[AovPixel]) -> Self {
+        return Self {
+            aov: *aov,
+            pixels: pixels.iter().map(|pixel| pixel.value(aov)).collect(),
+        };
+    }
+
+    // Maps the values into [0, 1] for viewing the pass as an 8-bit image: normals from [-1, 1],
+    // depth, sample counts and positions relative to the range they cover in the image.
+    pub fn visualize(&self) -> Vec<color::Color> {
+        let one = color::Color::new(1.0, 1.0, 1.0);
+        return match self.aov {
+            Aov::Albedo | Aov::Uv => self.pixels.clone(),
+            Aov::Normal => self.pixels.iter().map(|n| (n + &one) * 0.5).collect(),
+            Aov::Depth | Aov::Samples => {
+                let max = self
+                    .pixels
+                    .iter()
+                    .map(|d| d.x())
+                    .fold(0.0, RayTracingFloat::max);
+                let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+                self.pixels.iter().map(|d| d * scale).collect()
+            }
+            Aov::Position => {
+                let mut min = color::Color::new(
+                    RayTracingFloat::INFINITY,
+                    RayTracingFloat::INFINITY,
+                    RayTracingFloat::INFINITY,
+                );
+                let mut max = -&min;
+                for p in &self.pixels {
+                    for c in 0..3 {
+                        min[c] = min[c].min(p[c]);
+                        max[c] = max[c].max(p[c]);
+                    }
+                }
+                let mut extent = &max - &min;
+                for c in 0..3 {
+                    extent[c] = if extent[c] > 0.0 { extent[c] } else { 1.0 };
+                }
+                self.pixels
+                    .iter()
+                    .map(|p| {
+                        let mut mapped = p - &min;
+                        for c in 0..3 {
+                            mapped[c] /= extent[c];
+                        }
+                        return mapped;
+                    })
+                    .collect()
+            }
+        };
+    }
+}
+
+// The file a pass is written to when the output format only holds a single image, e.g.
+// "render.png" becomes "render.depth.png".
+pub fn pass_path(path: &str, aov: &Aov) -> String {
+    let p = std::path::Path::new(path);
+    let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let file_name = match p.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}.{}.{}", stem, aov.name(), extension),
+        None => format!("{}.{}", stem, aov.name()),
+    };
+    return p.with_file_name(file_name).to_string_lossy().into_owned();
+}
@@ -1,8 +1,53 @@
 use super::ray;
-use super::utils;
+use super::sampler;
 use super::utils::RayTracingFloat;
 use super::vec3;
 
+// The user facing parameters a Camera is built from, kept around so that scenes can provide
+// defaults which are then selectively overridden.
+#[derive(Clone)]
+pub struct CameraSettings {
+    pub lookfrom: ray::Point,
+    pub lookat: ray::Point,
+    pub vup: ray::Vector,
+    pub vfov: RayTracingFloat, // vertical field-of-view in degrees
+    pub aperture: RayTracingFloat,
+    pub focus_dist: RayTracingFloat,
+    pub time0: RayTracingFloat, // shutter open time
+    pub time1: RayTracingFloat, // shutter close time
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        return Self {
+            lookfrom: ray::Point::new(0.0, 0.0, 0.0),
+            lookat: ray::Point::new(0.0, 0.0, -1.0),
+            vup: ray::Vector::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        };
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: &RayTracingFloat) -> Camera {
+        return Camera::new(
+            &self.lookfrom,
+            &self.lookat,
+            &self.vup,
+            &self.vfov,
+            aspect_ratio,
+            &self.aperture,
+            &self.focus_dist,
+            &self.time0,
+            &self.time1,
+        );
+    }
+}
+
 #[derive(Clone)]
 pub struct Camera {
     origin: ray::Point,
@@ -11,7 +56,6 @@ pub struct Camera {
     vertical: ray::Vector,
     u: ray::Vector,
     v: ray::Vector,
-    w: ray::Vector,
     lens_radius: RayTracingFloat,
     time0: RayTracingFloat, // shutter open time
     time1: RayTracingFloat, // shutter close time
@@ -50,15 +94,20 @@ impl Camera {
             vertical: vertical,
             u: u,
             v: v,
-            w: w,
             lens_radius: aperture / 2.0,
             time0: _time0.clone(),
             time1: _time1.clone(),
         };
     }
 
-    pub fn get_ray(&self, s: &RayTracingFloat, t: &RayTracingFloat) -> ray::Ray {
-        let rd = ray::Vector::random_in_unit_disk() * self.lens_radius;
+    pub fn get_ray(
+        &self,
+        s: &RayTracingFloat,
+        t: &RayTracingFloat,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> ray::Ray {
+        let rd = ray::Vector::sample_in_unit_disk(&sampler.get_2d()) * self.lens_radius;
+        let time = self.time0 + (self.time1 - self.time0) * sampler.get_1d();
         let offset = &self.u * rd.x() + &self.v * rd.y();
 
         return ray::Ray::new(
@@ -66,7 +115,7 @@ impl Camera {
             &self.lower_left_corner + &self.horizontal * s + &self.vertical * t
                 - &self.origin
                 - &offset,
-            utils::random_double(&self.time0, &self.time1),
+            time,
         );
     }
 }
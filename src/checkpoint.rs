@@ -0,0 +1,191 @@
+// Checkpoints of progressive renders, written after every pass so that an interrupted render can
+// be resumed. They hold everything accumulated for each pixel so far. The samplers derive every
+// sample from the seed, the pixel and the sample index, so the seed and the per-pixel sample
+// counts are all it takes to continue with the same random numbers as an uninterrupted render.
+//
+// The file is binary and little-endian: a magic number and version, the render settings which
+// have to stay the same when resuming, along with a fingerprint of the scene and the settings not
+// stored themselves, then the pixels row by row from the top.
+use super::color;
+use super::render;
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 8] = b"RTCHKPT\0";
+const VERSION: u64 = 2;
+
+//
+// Writer
+//
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        return Self { data: Vec::new() };
+    }
+
+    pub fn u64(&mut self, value: &u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, value: &f64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn color(&mut self, c: &color::Color) {
+        for i in 0..3 {
+            self.f64(&c[i]);
+        }
+    }
+
+    pub fn string(&mut self, s: &str) {
+        self.u64(&(s.len() as u64));
+        self.data.extend_from_slice(s.as_bytes());
+    }
+}
+
+//
+// Reader
+//
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        return Self {
+            data: data,
+            position: 0,
+        };
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.position < count {
+            return Err(String::from("checkpoint is truncated"));
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        return Ok(bytes);
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        return Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()));
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String> {
+        return Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()));
+    }
+
+    pub fn color(&mut self) -> Result<color::Color, String> {
+        return Ok(color::Color::new(self.f64()?, self.f64()?, self.f64()?));
+    }
+
+    pub fn string(&mut self) -> Result<String, String> {
+        let length = self.u64()? as usize;
+        return String::from_utf8(self.bytes(length)?.to_vec())
+            .map_err(|_| String::from("checkpoint holds an invalid string"));
+    }
+}
+
+// Identifies a scene and its render settings, given in text form, with the 64-bit FNV-1a hash
+// of the text. Unlike the standard library's hasher, it stays the same across Rust versions.
+pub fn fingerprint(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+fn encode(accumulation: &render::Accumulation) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.data.extend_from_slice(MAGIC);
+    w.u64(&VERSION);
+    w.u64(&(accumulation.image_width as u64));
+    w.u64(&(accumulation.image_height as u64));
+    w.u64(&accumulation.seed);
+    w.string(&accumulation.sampler);
+    w.u64(&(accumulation.pass_samples as u64));
+    w.u64(&(accumulation.collect_aovs as u64));
+    w.u64(&accumulation.fingerprint);
+    w.u64(&(accumulation.target_samples as u64));
+
+    for pixel in &accumulation.pixels {
+        w.color(&pixel.sum);
+        w.u64(&(pixel.samples as u64));
+        pixel.estimate.save(&mut w);
+        if accumulation.collect_aovs {
+            pixel.aov.save(&mut w);
+        }
+    }
+    return w.data;
+}
+
+fn decode(data: &[u8]) -> Result<render::Accumulation, String> {
+    if !data.starts_with(MAGIC) {
+        return Err(String::from("not a checkpoint file"));
+    }
+    let mut r = Reader::new(data);
+    r.bytes(MAGIC.len())?;
+    let version = r.u64()?;
+    if version != VERSION {
+        return Err(format!("unsupported checkpoint version {}", version));
+    }
+
+    let image_width = r.u64()? as usize;
+    let image_height = r.u64()? as usize;
+    let seed = r.u64()?;
+    let sampler = r.string()?;
+    let pass_samples = r.u64()? as usize;
+    let collect_aovs = r.u64()? != 0;
+    let fingerprint = r.u64()?;
+    // Every pixel takes up many bytes, so this catches sizes no file could hold before allocating.
+    if image_width
+        .checked_mul(image_height)
+        .is_none_or(|pixels| pixels > data.len())
+    {
+        return Err(String::from("checkpoint is truncated"));
+    }
+    let mut accumulation = render::Accumulation::new(
+        &image_width,
+        &image_height,
+        &seed,
+        &sampler,
+        &pass_samples,
+        &collect_aovs,
+        &fingerprint,
+    );
+    accumulation.target_samples = r.u64()? as usize;
+
+    for pixel in accumulation.pixels.iter_mut() {
+        pixel.sum = r.color()?;
+        pixel.samples = r.u64()? as usize;
+        pixel.estimate.load(&mut r)?;
+        if collect_aovs {
+            pixel.aov.load(&mut r)?;
+        }
+    }
+    if r.position != data.len() {
+        return Err(String::from("checkpoint has trailing data"));
+    }
+    return Ok(accumulation);
+}
+
+// Writes a checkpoint. It goes to a temporary file first, which then replaces the previous
+// checkpoint, so that being interrupted while writing does not lose it.
+pub fn save(path: &str, accumulation: &render::Accumulation) -> Result<(), String> {
+    let temporary = format!("{}.tmp", path);
+    std::fs::write(&temporary, encode(accumulation))
+        .map_err(|err| format!("failed to write checkpoint {}: {}", temporary, err))?;
+    return std::fs::rename(&temporary, path)
+        .map_err(|err| format!("failed to write checkpoint {}: {}", path, err));
+}
+
+pub fn load(path: &str) -> Result<render::Accumulation, String> {
+    let data = std::fs::read(path)
+        .map_err(|err| format!("failed to read checkpoint {}: {}", path, err))?;
+    return decode(&data).map_err(|err| format!("{}: {}", path, err));
+}
@@ -0,0 +1,511 @@
+use super::adaptive;
+use super::aov;
+use super::camera;
+use super::color;
+use super::denoise;
+use super::environment;
+use super::filter;
+use super::ray;
+use super::render;
+use super::sampler;
+use super::scenes;
+use super::tonemap;
+use super::utils::RayTracingFloat;
+
+pub const DEFAULT_OUTPUT: &str = "image.png";
+
+pub struct Options {
+    pub scene: String,
+    pub scene_file: Option<String>,
+    pub image_width: Option<usize>,
+    pub image_height: Option<usize>,
+    pub aspect_ratio: Option<RayTracingFloat>,
+    pub samples_per_pixel: Option<usize>,
+    pub adaptive: Option<adaptive::AdaptiveSettings>,
+    pub pass_samples: Option<usize>,
+    pub time_limit: Option<RayTracingFloat>,
+    pub checkpoint: Option<String>,
+    pub resume: Option<String>,
+    pub sampler: String,
+    pub seed: u64,
+    pub filter: String,
+    pub filter_radius: Option<RayTracingFloat>,
+    pub max_depth: Option<u32>,
+    pub rr_min_depth: u32,
+    pub lookfrom: Option<ray::Point>,
+    pub lookat: Option<ray::Point>,
+    pub vup: Option<ray::Vector>,
+    pub vfov: Option<RayTracingFloat>,
+    pub aperture: Option<RayTracingFloat>,
+    pub focus_dist: Option<RayTracingFloat>,
+    pub environment: Option<String>,
+    pub environment_settings: environment::EnvironmentSettings,
+    pub num_threads: Option<usize>,
+    pub mis: render::MisHeuristic,
+    pub output: String,
+    pub tonemap: String,
+    pub exposure: RayTracingFloat,
+    pub white_point: Option<RayTracingFloat>,
+    pub color_space: color::ColorSpace,
+    pub aovs: Vec<aov::Aov>,
+    pub denoise: Option<denoise::DenoiseSettings>,
+    pub save_scene: Option<String>,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+impl Options {
+    fn new() -> Self {
+        return Self {
+            scene: scenes::DEFAULT_SCENE.to_string(),
+            scene_file: None,
+            image_width: None,
+            image_height: None,
+            aspect_ratio: None,
+            samples_per_pixel: None,
+            adaptive: None,
+            pass_samples: None,
+            time_limit: None,
+            checkpoint: None,
+            resume: None,
+            sampler: sampler::DEFAULT_SAMPLER.to_string(),
+            seed: 0,
+            filter: filter::DEFAULT_FILTER.to_string(),
+            filter_radius: None,
+            max_depth: None,
+            rr_min_depth: render::DEFAULT_RR_MIN_DEPTH,
+            lookfrom: None,
+            lookat: None,
+            vup: None,
+            vfov: None,
+            aperture: None,
+            focus_dist: None,
+            environment: None,
+            environment_settings: environment::EnvironmentSettings::default(),
+            num_threads: None,
+            mis: render::DEFAULT_MIS_HEURISTIC,
+            output: DEFAULT_OUTPUT.to_string(),
+            tonemap: tonemap::DEFAULT_OPERATOR.to_string(),
+            exposure: 0.0,
+            white_point: None,
+            color_space: color::ColorSpace::srgb(),
+            aovs: Vec::new(),
+            denoise: None,
+            save_scene: None,
+        };
+    }
+
+    // Works out the final image width, height and aspect ratio. Whatever is not given on the
+    // command line is derived from the other two settings, falling back to the scene defaults.
+    pub fn image_size(&self, scene: &scenes::Scene) -> (usize, usize, RayTracingFloat) {
+        let aspect_ratio = self.aspect_ratio.unwrap_or(scene.aspect_ratio);
+        let (width, height) = match (self.image_width, self.image_height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, (w as RayTracingFloat / aspect_ratio) as usize),
+            (None, Some(h)) => ((h as RayTracingFloat * aspect_ratio) as usize, h),
+            (None, None) => (
+                scene.image_width,
+                (scene.image_width as RayTracingFloat / aspect_ratio) as usize,
+            ),
+        };
+        let (width, height) = (width.max(1), height.max(1));
+        return (
+            width,
+            height,
+            width as RayTracingFloat / height as RayTracingFloat,
+        );
+    }
+
+    pub fn camera_settings(&self, defaults: &camera::CameraSettings) -> camera::CameraSettings {
+        let mut settings = defaults.clone();
+        if let Some(lookfrom) = &self.lookfrom {
+            settings.lookfrom = lookfrom.clone();
+        }
+        if let Some(lookat) = &self.lookat {
+            settings.lookat = lookat.clone();
+        }
+        if let Some(vup) = &self.vup {
+            settings.vup = vup.clone();
+        }
+        if let Some(vfov) = self.vfov {
+            settings.vfov = vfov;
+        }
+        if let Some(aperture) = self.aperture {
+            settings.aperture = aperture;
+        }
+        if let Some(focus_dist) = self.focus_dist {
+            settings.focus_dist = focus_dist;
+        }
+        return settings;
+    }
+
+    pub fn filter(&self) -> std::sync::Arc<dyn filter::Filter> {
+        // The filter name has been checked while parsing the arguments.
+        let filter_type = filter::find_filter(&self.filter).unwrap();
+        return (filter_type.build)(&self.filter_radius.unwrap_or(filter_type.default_radius));
+    }
+
+    pub fn tone_mapping(&self) -> tonemap::ToneMapping {
+        // The operator name has been checked while parsing the arguments.
+        let operator = tonemap::find_operator(&self.tonemap).unwrap();
+        return tonemap::ToneMapping::new(self.exposure, (operator.build)(&self.white_point));
+    }
+}
+
+pub fn usage() -> String {
+    let mut text = String::from(
+        "Usage: ray_trace [OPTIONS]
+
+Options:
+    -s, --scene <NAME>          built-in scene to render [default: cornell_box]
+    -f, --scene-file <FILE>     scene description file to render instead of a built-in scene
+    -W, --width <PIXELS>        image width
+    -H, --height <PIXELS>       image height
+    -a, --aspect-ratio <RATIO>  image aspect ratio, e.g. 1.5 or 3:2
+    -n, --spp <COUNT>           samples per pixel, the most any pixel gets with --adaptive
+        --adaptive              keep sampling only the pixels that are still noisy
+        --min-spp <COUNT>       samples every pixel gets with --adaptive, and between checks of
+                                its noise [default: 32]
+        --adaptive-threshold <VALUE>
+                                noise (standard error) at which a pixel is done [default: 0.005]
+        --pass-spp <COUNT>      render in passes adding this many samples per pixel each
+                                [default: all samples in one pass, 16 with --time-limit]
+        --time-limit <SECONDS>  keep adding passes until the time is up, then write the image;
+                                --spp becomes optional and caps the samples per pixel
+        --checkpoint <FILE>     after every pass, save the render so far for --resume
+        --resume <FILE>         carry on from a checkpoint up to --spp samples per pixel, saving
+                                new checkpoints to the same file unless --checkpoint is given
+        --sampler <NAME>        how the samples of a pixel are spread out, see below; the
+                                stratified sampler only stratifies the samples within each
+                                pass of --pass-spp or --time-limit [default: sobol]
+        --seed <NUMBER>         seed for all random numbers, the same seed renders the same
+                                image [default: 0]
+        --filter <NAME>         pixel reconstruction filter, see below [default: box]
+        --filter-radius <PIXELS>
+                                how far from the pixel center samples count towards it
+    -d, --max-depth <COUNT>     maximum number of ray bounces
+        --rr-min-depth <COUNT>  bounces before paths may be ended early by Russian roulette
+                                [default: 3]
+        --lookfrom <X,Y,Z>      camera position
+        --lookat <X,Y,Z>        point the camera looks at
+        --vup <X,Y,Z>           camera up direction
+        --vfov <DEGREES>        vertical field of view, between 0 and 180
+        --aperture <SIZE>       lens aperture, 0 for a pinhole camera
+        --focus-dist <DIST>     distance to the plane in focus
+        --environment <NAME|FILE>
+                                light around the scene: a built-in environment, see below, or
+                                an equirectangular .hdr or .png image
+        --environment-rotation <DEGREES>
+                                turn an environment image around the vertical axis [default: 0]
+        --environment-intensity <VALUE>
+                                scale the brightness of the environment [default: 1]
+        --sun-elevation <DEGREES>
+                                height of the sun above the horizon for the daylight
+                                environment [default: 45]
+        --sun-azimuth <DEGREES> direction of the sun, from -z towards +x [default: 0]
+        --turbidity <VALUE>     haziness of the daylight sky, from 1.7 (clear) to 10 (hazy)
+                                [default: 3]
+    -j, --threads <COUNT>       number of render threads [default: all cores]
+        --mis <NAME>            how light samples and scattered rays are weighted against each
+                                other: balance or power [default: power]
+    -o, --output <FILE>         output image path, the format is chosen from the extension:
+                                .png (8-bit), .hdr (Radiance RGBE), .pfm (32-bit float) or
+                                .exr (OpenEXR, 32-bit float RGBA)
+                                [default: image.png]
+        --tonemap <NAME>        tone mapping operator for 8-bit output, see below
+                                [default: clamp]
+        --exposure <EV>         exposure adjustment in stops before tone mapping [default: 0]
+        --white-point <VALUE>   radiance mapped to white by reinhard-extended and hable
+        --primaries <NAME>      primaries of 8-bit output: rec709 or display-p3
+                                [default: rec709]
+        --transfer <NAME>       encoding of 8-bit output: srgb or linear [default: srgb]
+        --aov <LIST>            auxiliary passes to write along with the image, comma separated
+                                or all: depth, normal, albedo, position, uv and samples (the
+                                number of samples per pixel). EXR files store them as extra
+                                channels, other formats as separate files
+        --denoise               filter the noise out of the rendered image
+        --denoise-iterations <COUNT>
+                                number of filter passes, each covering twice the distance of
+                                the previous one [default: 5]
+        --denoise-strength <VALUE>
+                                how different colors may be and still get blended [default: 1]
+        --save-scene <FILE>     write the scene, including the settings given on the command
+                                line, to a scene file instead of rendering it
+    -h, --help                  print this help
+
+Settings not given on the command line are taken from the scene. The scene file format is
+described in docs/scene-format.md.
+
+Built-in scenes:
+",
+    );
+    for scene in scenes::BUILTIN_SCENES {
+        text += &format!("    {:<28}{}\n", scene.name, scene.description);
+    }
+    text += "\nEnvironments:\n";
+    for environment in environment::BUILTIN_ENVIRONMENTS {
+        text += &format!("    {:<28}{}\n", environment.name, environment.description);
+    }
+    text += "\nSamplers:\n";
+    for sampler in sampler::SAMPLERS {
+        text += &format!("    {:<28}{}\n", sampler.name, sampler.description);
+    }
+    text += "\nFilters:\n";
+    for filter in filter::FILTERS {
+        text += &format!("    {:<28}{}\n", filter.name, filter.description);
+    }
+    text += "\nTone mapping operators:\n";
+    for operator in tonemap::OPERATORS {
+        text += &format!("    {:<28}{}\n", operator.name, operator.description);
+    }
+    return text;
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    return value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag));
+}
+
+fn parse_vector(flag: &str, value: &str) -> Result<ray::Vector, String> {
+    let components = value
+        .split(',')
+        .map(|c| parse_number::<RayTracingFloat>(flag, c))
+        .collect::<Result<Vec<_>, _>>()?;
+    if components.len() != 3 {
+        return Err(format!("expected X,Y,Z for {}, got '{}'", flag, value));
+    }
+    return Ok(ray::Vector::new(
+        components[0],
+        components[1],
+        components[2],
+    ));
+}
+
+fn parse_aspect_ratio(flag: &str, value: &str) -> Result<RayTracingFloat, String> {
+    let ratio = match value.split_once(':') {
+        Some((w, h)) => {
+            parse_number::<RayTracingFloat>(flag, w)? / parse_number::<RayTracingFloat>(flag, h)?
+        }
+        None => parse_number::<RayTracingFloat>(flag, value)?,
+    };
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(format!("invalid value '{}' for {}", value, flag));
+    }
+    return Ok(ratio);
+}
+
+// A finite number for which valid holds.
+fn parse_checked(
+    flag: &str,
+    value: &str,
+    valid: fn(&RayTracingFloat) -> bool,
+) -> Result<RayTracingFloat, String> {
+    let number = parse_number::<RayTracingFloat>(flag, value)?;
+    if !number.is_finite() || !valid(&number) {
+        return Err(format!("invalid value '{}' for {}", value, flag));
+    }
+    return Ok(number);
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<usize, String> {
+    let count = parse_number::<usize>(flag, value)?;
+    if count == 0 {
+        return Err(format!("{} must be greater than zero", flag));
+    }
+    return Ok(count);
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut options = Options::new();
+
+    while let Some(arg) = args.next() {
+        // Accept both "--flag value" and "--flag=value".
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            return match inline_value.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(format!("missing value for {}", flag)),
+            };
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-s" | "--scene" => options.scene = value()?,
+            "-f" | "--scene-file" => options.scene_file = Some(value()?),
+            "-W" | "--width" => options.image_width = Some(parse_positive(&flag, &value()?)?),
+            "-H" | "--height" => options.image_height = Some(parse_positive(&flag, &value()?)?),
+            "-a" | "--aspect-ratio" => {
+                options.aspect_ratio = Some(parse_aspect_ratio(&flag, &value()?)?)
+            }
+            "-n" | "--spp" => options.samples_per_pixel = Some(parse_positive(&flag, &value()?)?),
+            "--adaptive" => {
+                options.adaptive.get_or_insert_with(Default::default);
+            }
+            "--min-spp" => {
+                let min_samples = parse_positive(&flag, &value()?)?;
+                options
+                    .adaptive
+                    .get_or_insert_with(Default::default)
+                    .min_samples = min_samples;
+            }
+            "--adaptive-threshold" => {
+                let threshold = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !threshold.is_finite() || threshold <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                options
+                    .adaptive
+                    .get_or_insert_with(Default::default)
+                    .threshold = threshold;
+            }
+            "--pass-spp" => options.pass_samples = Some(parse_positive(&flag, &value()?)?),
+            "--time-limit" => {
+                let seconds = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !seconds.is_finite() || seconds <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                options.time_limit = Some(seconds);
+            }
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--resume" => options.resume = Some(value()?),
+            "--sampler" => {
+                options.sampler = value()?;
+                if sampler::find_sampler(&options.sampler).is_none() {
+                    return Err(format!("unknown sampler '{}'", options.sampler));
+                }
+            }
+            "--seed" => options.seed = parse_number(&flag, &value()?)?,
+            "--filter" => {
+                options.filter = value()?;
+                if filter::find_filter(&options.filter).is_none() {
+                    return Err(format!("unknown filter '{}'", options.filter));
+                }
+            }
+            "--filter-radius" => {
+                let radius = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !radius.is_finite() || radius <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                options.filter_radius = Some(radius);
+            }
+            "-d" | "--max-depth" => options.max_depth = Some(parse_number(&flag, &value()?)?),
+            "--rr-min-depth" => options.rr_min_depth = parse_number(&flag, &value()?)?,
+            "--lookfrom" => options.lookfrom = Some(parse_vector(&flag, &value()?)?),
+            "--lookat" => options.lookat = Some(parse_vector(&flag, &value()?)?),
+            "--vup" => options.vup = Some(parse_vector(&flag, &value()?)?),
+            "--vfov" => {
+                options.vfov = Some(parse_checked(&flag, &value()?, |v| *v > 0.0 && *v < 180.0)?)
+            }
+            "--aperture" => {
+                options.aperture = Some(parse_checked(&flag, &value()?, |a| *a >= 0.0)?)
+            }
+            "--focus-dist" => {
+                options.focus_dist = Some(parse_checked(&flag, &value()?, |d| *d > 0.0)?)
+            }
+            "--environment" => options.environment = Some(value()?),
+            "--environment-rotation" => {
+                options.environment_settings.rotation = parse_number(&flag, &value()?)?
+            }
+            "--environment-intensity" => {
+                let intensity = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !intensity.is_finite() || intensity < 0.0 {
+                    return Err(format!("{} must not be negative", flag));
+                }
+                options.environment_settings.intensity = intensity;
+            }
+            "--sun-elevation" => {
+                let elevation = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !(0.0..=90.0).contains(&elevation) {
+                    return Err(format!("{} must be between 0 and 90 degrees", flag));
+                }
+                options.environment_settings.sun_elevation = elevation;
+            }
+            "--sun-azimuth" => {
+                options.environment_settings.sun_azimuth = parse_number(&flag, &value()?)?
+            }
+            "--turbidity" => {
+                let turbidity = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !(1.7..=10.0).contains(&turbidity) {
+                    return Err(format!("{} must be between 1.7 and 10", flag));
+                }
+                options.environment_settings.turbidity = turbidity;
+            }
+            "-j" | "--threads" => options.num_threads = Some(parse_positive(&flag, &value()?)?),
+            "--mis" => {
+                let name = value()?;
+                options.mis = render::MisHeuristic::from_name(&name)
+                    .ok_or_else(|| format!("unknown MIS heuristic '{}'", name))?;
+            }
+            "-o" | "--output" => options.output = value()?,
+            "--tonemap" => {
+                options.tonemap = value()?;
+                if tonemap::find_operator(&options.tonemap).is_none() {
+                    return Err(format!(
+                        "unknown tone mapping operator '{}'",
+                        options.tonemap
+                    ));
+                }
+            }
+            "--exposure" => options.exposure = parse_checked(&flag, &value()?, |_| true)?,
+            "--white-point" => {
+                let white = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !white.is_finite() || white <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                options.white_point = Some(white);
+            }
+            "--primaries" => {
+                let name = value()?;
+                options.color_space.primaries = color::Primaries::from_name(&name)
+                    .ok_or_else(|| format!("unknown primaries '{}'", name))?;
+            }
+            "--transfer" => {
+                let name = value()?;
+                options.color_space.transfer = color::TransferFunction::from_name(&name)
+                    .ok_or_else(|| format!("unknown transfer function '{}'", name))?;
+            }
+            "--aov" => options.aovs = aov::parse_list(&value()?)?,
+            "--denoise" => {
+                options.denoise.get_or_insert_with(Default::default);
+            }
+            "--denoise-iterations" => {
+                let iterations = parse_positive(&flag, &value()?)?;
+                options
+                    .denoise
+                    .get_or_insert_with(Default::default)
+                    .iterations = iterations;
+            }
+            "--denoise-strength" => {
+                let strength = parse_number::<RayTracingFloat>(&flag, &value()?)?;
+                if !strength.is_finite() || strength <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                let settings = options.denoise.get_or_insert_with(Default::default);
+                settings.sigma_color = denoise::DenoiseSettings::default().sigma_color * strength;
+            }
+            "--save-scene" => options.save_scene = Some(value()?),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    if options.image_width.is_some()
+        && options.image_height.is_some()
+        && options.aspect_ratio.is_some()
+    {
+        return Err(String::from(
+            "--width, --height and --aspect-ratio cannot all be given at once",
+        ));
+    }
+
+    return Ok(Command::Render(Box::new(options)));
+}
@@ -1,7 +1,11 @@
+use super::tonemap;
 use super::utils;
 use super::utils::RayTracingFloat;
 use super::vec3;
 
+// Colors are linear (scene referred) values in the Rec.709 / sRGB primaries with a D65 white point.
+// Both radiance and reflectance use this working space, it is only converted to an output color
+// space when an image is written.
 pub type Color = vec3::Vec3<RayTracingFloat>;
 
 impl Color {
@@ -18,18 +22,119 @@ impl Color {
     }
 }
 
-pub fn write_color(pixel_color: &Color, samples_per_pixel: &usize) -> rgb::RGBA8 {
-    let samples = *samples_per_pixel as RayTracingFloat;
+//
+// Output Color Space
+//
+#[derive(Clone, Copy, PartialEq)]
+pub enum Primaries {
+    Rec709, // same as sRGB, the working space
+    DisplayP3,
+}
+
+impl Primaries {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "rec709" => Some(Primaries::Rec709),
+            "display-p3" => Some(Primaries::DisplayP3),
+            _ => None,
+        };
+    }
+
+    // Converts a linear color from the working space to these primaries.
+    pub fn convert(&self, c: &Color) -> Color {
+        return match self {
+            Primaries::Rec709 => c.clone(),
+            // Both spaces share the D65 white point, so no chromatic adaptation is needed.
+            Primaries::DisplayP3 => Color::new(
+                0.8224621 * c.r() + 0.1775380 * c.g(),
+                0.0331941 * c.r() + 0.9668058 * c.g(),
+                0.0170827 * c.r() + 0.0723974 * c.g() + 0.9105199 * c.b(),
+            ),
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    Srgb,   // piecewise sRGB curve, also used by Display P3
+    Linear, // no encoding
+}
+
+impl TransferFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "srgb" => Some(TransferFunction::Srgb),
+            "linear" => Some(TransferFunction::Linear),
+            _ => None,
+        };
+    }
+
+    // Encodes a linear value in [0, 1] for display (the opto-electronic transfer function).
+    pub fn encode(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return match self {
+            TransferFunction::Srgb => {
+                if *x <= 0.0031308 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Linear => *x,
+        };
+    }
+
+    // Turns an encoded value back into a linear one, the inverse of encode.
+    pub fn decode(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return match self {
+            TransferFunction::Srgb => {
+                if *x <= 0.04045 {
+                    x / 12.92
+                } else {
+                    ((x + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            TransferFunction::Linear => *x,
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct ColorSpace {
+    pub primaries: Primaries,
+    pub transfer: TransferFunction,
+}
+
+impl ColorSpace {
+    pub fn new(primaries: Primaries, transfer: TransferFunction) -> Self {
+        return Self {
+            primaries: primaries,
+            transfer: transfer,
+        };
+    }
+
+    pub fn srgb() -> Self {
+        return Self::new(Primaries::Rec709, TransferFunction::Srgb);
+    }
+}
+
+pub fn write_color(
+    pixel_color: &Color,
+    tone_mapping: &tonemap::ToneMapping,
+    color_space: &ColorSpace,
+) -> rgb::RGBA8 {
+    // Bring the color, already averaged over the pixel's samples, into the displayable range.
+    let mapped = tone_mapping.apply(pixel_color);
+    let mapped = color_space.primaries.convert(&mapped);
 
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let r = (pixel_color.r() / samples).sqrt();
-    let g = (pixel_color.g() / samples).sqrt();
-    let b = (pixel_color.b() / samples).sqrt();
+    // Encode for display.
+    let r = color_space.transfer.encode(&mapped.r());
+    let g = color_space.transfer.encode(&mapped.g());
+    let b = color_space.transfer.encode(&mapped.b());
 
     // Write the translated [0,255] value of each color component.
     let ir = (256.0 * utils::clamp(r, 0.0, 0.999)) as u8;
     let ig = (256.0 * utils::clamp(g, 0.0, 0.999)) as u8;
     let ib = (256.0 * utils::clamp(b, 0.0, 0.999)) as u8;
 
-    return rgb::RGBA8::new(ir, ig, ib, std::u8::MAX);
+    return rgb::RGBA8::new(ir, ig, ib, u8::MAX);
 }
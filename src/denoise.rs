@@ -0,0 +1,189 @@
+// Edge-avoiding à-trous wavelet denoiser, after Dammertz et al., "Edge-Avoiding À-Trous Wavelet
+// Transform for fast Global Illumination Filtering" (HPG 2010).
+//
+// The image is blurred repeatedly with a 5x5 kernel whose taps are spread further apart on every
+// iteration. Each tap is weighted by how similar its color, normal and depth are to the center
+// pixel, so that noise is smoothed out while edges between surfaces are kept. Filtering is done
+// on the lighting with the albedo divided out, which keeps textures sharp.
+use super::aov;
+use super::color;
+use super::ray;
+use super::utils::RayTracingFloat;
+use super::vec3;
+
+#[derive(Clone)]
+pub struct DenoiseSettings {
+    pub iterations: usize,
+    pub sigma_color: RayTracingFloat, // tolerance for color differences
+    pub sigma_normal: RayTracingFloat, // tolerance for normal differences
+    pub sigma_depth: RayTracingFloat, // tolerance for relative depth differences
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        return Self {
+            iterations: 5,
+            sigma_color: 2.5,
+            sigma_normal: 0.1,
+            sigma_depth: 0.05,
+        };
+    }
+}
+
+// B3 spline kernel
+const KERNEL: [RayTracingFloat; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Keeps surfaces with a black albedo from dividing by zero.
+const ALBEDO_EPSILON: RayTracingFloat = 1e-3;
+
+struct Features {
+    normal: ray::Vector,
+    depth: RayTracingFloat,
+}
+
+// Compresses the range of the radiance, so that bright lights do not dominate color differences.
+fn compress(c: &color::Color) -> color::Color {
+    return color::Color::new(
+        c.x() / (1.0 + c.x()),
+        c.y() / (1.0 + c.y()),
+        c.z() / (1.0 + c.z()),
+    );
+}
+
+fn filter_rows(
+    rows: std::ops::Range<usize>,
+    input: &[color::Color],
+    features: &[Features],
+    image_width: &usize,
+    image_height: &usize,
+    step: &usize,
+    settings: &DenoiseSettings,
+    sigma_color: &RayTracingFloat,
+    output: &mut [color::Color],
+) {
+    let mut index = 0;
+    for y in rows {
+        for x in 0..*image_width {
+            let center = y * image_width + x;
+            let center_color = compress(&input[center]);
+            let center_features = &features[center];
+
+            let mut sum = color::Color::zero();
+            let mut weight_sum = 0.0;
+            for (ky, kernel_y) in KERNEL.iter().enumerate() {
+                let qy = y as isize + (ky as isize - 2) * *step as isize;
+                if qy < 0 || qy >= *image_height as isize {
+                    continue;
+                }
+                for (kx, kernel_x) in KERNEL.iter().enumerate() {
+                    let qx = x as isize + (kx as isize - 2) * *step as isize;
+                    if qx < 0 || qx >= *image_width as isize {
+                        continue;
+                    }
+                    let q = qy as usize * image_width + qx as usize;
+                    let q_features = &features[q];
+
+                    let color_distance = (compress(&input[q]) - &center_color).length_squared();
+                    let normal_distance =
+                        (1.0 - vec3::dot(&center_features.normal, &q_features.normal)).max(0.0);
+                    let depth_distance = (center_features.depth - q_features.depth).abs()
+                        / (center_features.depth.max(q_features.depth) + 1e-4)
+                        / *step as RayTracingFloat;
+
+                    let weight = kernel_x
+                        * kernel_y
+                        * (-color_distance / (sigma_color * sigma_color)
+                            - normal_distance / settings.sigma_normal
+                            - depth_distance / settings.sigma_depth)
+                            .exp();
+                    sum += &input[q] * weight;
+                    weight_sum += weight;
+                }
+            }
+
+            // The center tap always has a weight of 9/64, so the sum is never zero.
+            output[index] = sum / weight_sum;
+            index += 1;
+        }
+    }
+}
+
+// Denoises an image given as linear radiance, using the feature buffers collected while rendering
+// it. Both are stored row by row from the top of the image.
+pub fn denoise(
+    pixels: &[color::Color],
+    aovs: &[aov::AovPixel],
+    image_width: &usize,
+    image_height: &usize,
+    settings: &DenoiseSettings,
+    num_threads: &usize,
+) -> Vec<color::Color> {
+    let albedo = aovs
+        .iter()
+        .map(|pixel| pixel.value(&aov::Aov::Albedo))
+        .collect::<Vec<_>>();
+    let features = aovs
+        .iter()
+        .map(|pixel| Features {
+            normal: pixel.value(&aov::Aov::Normal),
+            depth: pixel.value(&aov::Aov::Depth).x(),
+        })
+        .collect::<Vec<_>>();
+
+    // Divide out the albedo, leaving the lighting which is smooth across textured surfaces.
+    let mut current = pixels
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| {
+            color::Color::new(
+                c.x() / (a.x() + ALBEDO_EPSILON),
+                c.y() / (a.y() + ALBEDO_EPSILON),
+                c.z() / (a.z() + ALBEDO_EPSILON),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let num_threads = (*num_threads).max(1);
+    let rows_per_thread = image_height.div_ceil(num_threads);
+    let mut next = vec![color::Color::zero(); current.len()];
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        // Later iterations see smoother input, so they need to be less tolerant of differences.
+        let sigma_color = settings.sigma_color / (1 << iteration) as RayTracingFloat;
+
+        std::thread::scope(|scope| {
+            for (chunk, output) in next.chunks_mut(rows_per_thread * image_width).enumerate() {
+                let rows =
+                    chunk * rows_per_thread..((chunk + 1) * rows_per_thread).min(*image_height);
+                let (input, features) = (&current, &features);
+                scope.spawn(move || {
+                    filter_rows(
+                        rows,
+                        input,
+                        features,
+                        image_width,
+                        image_height,
+                        &step,
+                        settings,
+                        &sigma_color,
+                        output,
+                    );
+                });
+            }
+        });
+        std::mem::swap(&mut current, &mut next);
+    }
+
+    // Put the albedo back.
+    return current
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| {
+            color::Color::new(
+                c.x() * (a.x() + ALBEDO_EPSILON),
+                c.y() * (a.y() + ALBEDO_EPSILON),
+                c.z() * (a.z() + ALBEDO_EPSILON),
+            )
+        })
+        .collect();
+}
@@ -0,0 +1,250 @@
+// Density fields, which say how thick a heterogeneous volume is at each point of its box. Fields
+// are defined over the unit cube [0, 1]³, which the volume stretches over its box.
+use super::ray;
+use super::sampler;
+use super::utils;
+use super::utils::RayTracingFloat;
+use super::vec3;
+use std::convert::TryInto;
+
+pub trait DensityField: Send + Sync {
+    // Density at a point of the unit cube, relative to the density of the volume.
+    fn density(&self, p: &ray::Point) -> RayTracingFloat;
+
+    // No point has a higher density. Delta and ratio tracking take steps as if the whole volume
+    // were this dense, so a tight bound makes them faster.
+    fn max_density(&self) -> RayTracingFloat;
+
+    // Gives access to the concrete type, e.g. for writing the scene graph out to a file.
+    fn as_any(&self) -> &dyn std::any::Any;
+}
+
+//
+// Density Grid
+//
+// Densities on a grid of voxels, read from a file and interpolated trilinearly between the voxel
+// centers.
+//
+// The file is binary and little-endian: the magic number "DENSITY\0", the number of voxels along
+// x, y and z as 32-bit unsigned integers, then a 32-bit float per voxel, with x changing fastest
+// and z slowest. Voxel (0, 0, 0) lies at the minimum corner of the box.
+const GRID_MAGIC: &[u8; 8] = b"DENSITY\0";
+
+pub struct DensityGrid {
+    path: String,
+    size: [usize; 3],
+    values: Vec<RayTracingFloat>,
+    max_density: RayTracingFloat,
+}
+
+impl DensityGrid {
+    pub fn load(path: &str) -> Result<Self, String> {
+        return std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| Self::decode(path, &data))
+            .map_err(|err| format!("{}: {}", path, err));
+    }
+
+    fn decode(path: &str, data: &[u8]) -> Result<Self, String> {
+        if !data.starts_with(GRID_MAGIC) {
+            return Err(String::from("not a density grid file"));
+        }
+        let header_size = GRID_MAGIC.len() + 3 * 4;
+        if data.len() < header_size {
+            return Err(String::from("header is truncated"));
+        }
+        let mut size = [0; 3];
+        for (i, s) in size.iter_mut().enumerate() {
+            let start = GRID_MAGIC.len() + 4 * i;
+            *s = u32::from_le_bytes(data[start..start + 4].try_into().unwrap()) as usize;
+        }
+        if size.contains(&0) {
+            return Err(String::from("the grid has no voxels"));
+        }
+        let count = size[0].saturating_mul(size[1]).saturating_mul(size[2]);
+        if data.len() - header_size != count.saturating_mul(4) {
+            return Err(format!(
+                "expected {} x {} x {} voxels of 4 bytes after the header, found {} bytes",
+                size[0],
+                size[1],
+                size[2],
+                data.len() - header_size
+            ));
+        }
+
+        let mut values = Vec::with_capacity(count);
+        for bytes in data[header_size..].chunks(4) {
+            let value = f32::from_le_bytes(bytes.try_into().unwrap()) as RayTracingFloat;
+            if !value.is_finite() {
+                return Err(String::from("densities must be finite numbers"));
+            }
+            // Negative densities have no meaning, they count as empty space.
+            values.push(value.max(0.0));
+        }
+        let max_density = values.iter().cloned().fold(0.0, RayTracingFloat::max);
+
+        return Ok(Self {
+            path: String::from(path),
+            size: size,
+            values: values,
+            max_density: max_density,
+        });
+    }
+
+    pub fn path(&self) -> &str {
+        return &self.path;
+    }
+
+    fn voxel(&self, x: &usize, y: &usize, z: &usize) -> RayTracingFloat {
+        return self.values[(z * self.size[1] + y) * self.size[0] + x];
+    }
+}
+
+impl DensityField for DensityGrid {
+    fn density(&self, p: &ray::Point) -> RayTracingFloat {
+        // The two voxels to interpolate between along each axis, and how far p is from the first,
+        // keeping the density of the outer voxels up to the faces of the box.
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut t = [0.0; 3];
+        for axis in 0..3 {
+            let last = (self.size[axis] - 1) as RayTracingFloat;
+            let x = utils::clamp(
+                p[axis] * self.size[axis] as RayTracingFloat - 0.5,
+                0.0,
+                last,
+            );
+            lower[axis] = x.floor() as usize;
+            upper[axis] = (lower[axis] + 1).min(self.size[axis] - 1);
+            t[axis] = x - x.floor();
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut voxel = [0; 3];
+            for axis in 0..3 {
+                if corner & (1 << axis) != 0 {
+                    voxel[axis] = upper[axis];
+                    weight *= t[axis];
+                } else {
+                    voxel[axis] = lower[axis];
+                    weight *= 1.0 - t[axis];
+                }
+            }
+            if weight > 0.0 {
+                density += weight * self.voxel(&voxel[0], &voxel[1], &voxel[2]);
+            }
+        }
+        return density;
+    }
+
+    fn max_density(&self) -> RayTracingFloat {
+        return self.max_density;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Noise Density
+//
+// A puff of cloud filling the box: dense in the middle, thinning out towards the faces of the box,
+// with its edges broken up by fractal Perlin noise. The frequency is the number of noise features
+// across the box, every further octave adds details of half the size.
+pub struct NoiseDensity {
+    frequency: RayTracingFloat,
+    octaves: u32,
+}
+
+impl NoiseDensity {
+    pub fn new(frequency: RayTracingFloat, octaves: u32) -> Self {
+        return Self {
+            frequency: frequency,
+            octaves: octaves,
+        };
+    }
+
+    pub fn frequency(&self) -> &RayTracingFloat {
+        return &self.frequency;
+    }
+
+    pub fn octaves(&self) -> &u32 {
+        return &self.octaves;
+    }
+
+    fn fade(t: &RayTracingFloat) -> RayTracingFloat {
+        return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    }
+
+    // The gradient at a lattice point, one of the 12 directions to the edges of a cube, picked by
+    // hashing the point so that no permutation table is needed.
+    fn gradient(lattice: &[i64; 3]) -> ray::Vector {
+        const GRADIENTS: [[RayTracingFloat; 3]; 12] = [
+            [1.0, 1.0, 0.0],
+            [-1.0, 1.0, 0.0],
+            [1.0, -1.0, 0.0],
+            [-1.0, -1.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [-1.0, 0.0, 1.0],
+            [1.0, 0.0, -1.0],
+            [-1.0, 0.0, -1.0],
+            [0.0, 1.0, 1.0],
+            [0.0, -1.0, 1.0],
+            [0.0, 1.0, -1.0],
+            [0.0, -1.0, -1.0],
+        ];
+        let g = GRADIENTS[(sampler::hash(&lattice.map(|i| i as u64)) % 12) as usize];
+        return ray::Vector::new(g[0], g[1], g[2]);
+    }
+
+    // Ken Perlin's improved gradient noise, "Improving Noise" (2002), roughly in [-1, 1].
+    fn perlin(p: &ray::Point) -> RayTracingFloat {
+        let base = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let f = p - &ray::Point::new(base[0], base[1], base[2]);
+        let u = [Self::fade(&f.x()), Self::fade(&f.y()), Self::fade(&f.z())];
+
+        let mut sum = 0.0;
+        for corner in 0..8 {
+            let mut lattice = [0; 3];
+            let mut offset = ray::Vector::zero();
+            let mut weight = 1.0;
+            for axis in 0..3 {
+                let step = (corner >> axis) & 1;
+                lattice[axis] = base[axis] as i64 + step as i64;
+                offset[axis] = f[axis] - step as RayTracingFloat;
+                weight *= if step == 1 { u[axis] } else { 1.0 - u[axis] };
+            }
+            sum += weight * vec3::dot(&Self::gradient(&lattice), &offset);
+        }
+        return sum;
+    }
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, p: &ray::Point) -> RayTracingFloat {
+        let mut noise = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        for _ in 0..self.octaves {
+            noise += amplitude * Self::perlin(&(p * frequency));
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        // 1 in the middle of the box, 0 at the centers of its faces.
+        let centered = p * 2.0 - ray::Vector::new(1.0, 1.0, 1.0);
+        let falloff = 1.0 - centered.length_squared();
+        return utils::clamp(falloff + noise, 0.0, 1.0);
+    }
+
+    fn max_density(&self) -> RayTracingFloat {
+        return 1.0;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
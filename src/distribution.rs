@@ -0,0 +1,114 @@
+// Piecewise constant distributions, for drawing samples in proportion to tabulated values such as
+// the brightness of the pixels of an image.
+use super::utils::RayTracingFloat;
+
+//
+// Distribution 1D
+//
+// A density over [0, 1) made of equally wide steps, each as high as its value.
+pub struct Distribution1D {
+    values: Vec<RayTracingFloat>,
+    cdf: Vec<RayTracingFloat>, // cdf[i] is the probability of a sample falling below step i
+    integral: RayTracingFloat, // of the values over [0, 1)
+}
+
+impl Distribution1D {
+    // Negative values count as zero. If all values are zero, every step is equally likely.
+    pub fn new(values: &[RayTracingFloat]) -> Self {
+        let count = values.len().max(1);
+        let mut values = values.iter().map(|v| v.max(0.0)).collect::<Vec<_>>();
+        values.resize(count, 0.0);
+
+        let mut cdf = Vec::with_capacity(count + 1);
+        cdf.push(0.0);
+        for (i, v) in values.iter().enumerate() {
+            cdf.push(cdf[i] + v / count as RayTracingFloat);
+        }
+        let integral = cdf[count];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as RayTracingFloat / count as RayTracingFloat
+            };
+        }
+
+        return Self {
+            values: values,
+            cdf: cdf,
+            integral: integral,
+        };
+    }
+
+    pub fn count(&self) -> usize {
+        return self.values.len();
+    }
+
+    pub fn integral(&self) -> RayTracingFloat {
+        return self.integral;
+    }
+
+    // Maps a uniform random number to a position in [0, 1), returning it with the index of its
+    // step and the density it was drawn with.
+    pub fn sample(&self, u: &RayTracingFloat) -> (RayTracingFloat, usize, RayTracingFloat) {
+        let count = self.count();
+        // The last step whose cdf is not above u; steps without any weight are never picked.
+        let index = (self.cdf.partition_point(|c| c <= u) - 1).min(count - 1);
+        let step_probability = self.cdf[index + 1] - self.cdf[index];
+        let within_step = if step_probability > 0.0 {
+            ((u - self.cdf[index]) / step_probability).min(1.0 - RayTracingFloat::EPSILON)
+        } else {
+            0.5
+        };
+        let x = (index as RayTracingFloat + within_step) / count as RayTracingFloat;
+        return (x, index, step_probability * count as RayTracingFloat);
+    }
+
+    // Density of the position x in [0, 1).
+    pub fn pdf(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        let count = self.count();
+        let index = ((x * count as RayTracingFloat) as usize).min(count - 1);
+        return (self.cdf[index + 1] - self.cdf[index]) * count as RayTracingFloat;
+    }
+}
+
+//
+// Distribution 2D
+//
+// A density over [0, 1)², given as a grid of values row by row. A row is drawn first, following the
+// sums of the rows, then a position within that row.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(values: &[RayTracingFloat], width: &usize, height: &usize) -> Self {
+        let rows = values
+            .chunks((*width).max(1))
+            .take(*height)
+            .map(Distribution1D::new)
+            .collect::<Vec<_>>();
+        let marginal =
+            Distribution1D::new(&rows.iter().map(|row| row.integral()).collect::<Vec<_>>());
+        return Self {
+            rows: rows,
+            marginal: marginal,
+        };
+    }
+
+    // Maps two uniform random numbers to a position (x, y) in [0, 1)², along with its density.
+    pub fn sample(
+        &self,
+        u: &(RayTracingFloat, RayTracingFloat),
+    ) -> ((RayTracingFloat, RayTracingFloat), RayTracingFloat) {
+        let (y, row, row_pdf) = self.marginal.sample(&u.1);
+        let (x, _, x_pdf) = self.rows[row].sample(&u.0);
+        return ((x, y), row_pdf * x_pdf);
+    }
+
+    pub fn pdf(&self, x: &RayTracingFloat, y: &RayTracingFloat) -> RayTracingFloat {
+        let row = ((y * self.rows.len() as RayTracingFloat) as usize).min(self.rows.len() - 1);
+        return self.marginal.pdf(y) * self.rows[row].pdf(x);
+    }
+}
@@ -0,0 +1,554 @@
+// Environments give the light arriving from infinitely far away, seen by every ray which does not
+// hit anything in the scene.
+use super::color;
+use super::distribution;
+use super::image_input;
+use super::onb;
+use super::ray;
+use super::utils::RayTracingFloat;
+use super::vec3;
+
+pub trait Environment: Send + Sync {
+    // Radiance arriving from the given direction, which need not be of unit length.
+    fn radiance(&self, direction: &ray::Vector) -> color::Color;
+
+    // Whether any light comes from the environment at all, which makes it worth sampling.
+    fn emits(&self) -> bool {
+        return true;
+    }
+
+    // Draws a direction towards the environment, ideally in proportion to the light arriving from
+    // it. By default, all directions are equally likely.
+    fn sample_direction(&self, u: &(RayTracingFloat, RayTracingFloat)) -> ray::Vector {
+        return ray::Vector::sample_unit_vector(u);
+    }
+
+    // Density, per unit solid angle, of sample_direction drawing the given direction.
+    fn pdf(&self, _direction: &ray::Vector) -> RayTracingFloat {
+        return 1.0 / (4.0 * std::f64::consts::PI);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any;
+}
+
+//
+// Constant
+//
+pub struct ConstantEnvironment {
+    color: color::Color,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: color::Color) -> Self {
+        return Self { color: color };
+    }
+
+    pub fn color(&self) -> &color::Color {
+        return &self.color;
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction: &ray::Vector) -> color::Color {
+        return self.color.clone();
+    }
+
+    fn emits(&self) -> bool {
+        return self.color.x() > 0.0 || self.color.y() > 0.0 || self.color.z() > 0.0;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Gradient
+//
+// Blends from one color straight down to another straight up, the sky of "Ray Tracing in One
+// Weekend" by default.
+pub struct GradientEnvironment {
+    bottom: color::Color,
+    top: color::Color,
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom: color::Color, top: color::Color) -> Self {
+        return Self {
+            bottom: bottom,
+            top: top,
+        };
+    }
+
+    pub fn sky() -> Self {
+        return Self::new(
+            color::Color::new(1.0, 1.0, 1.0),
+            color::Color::new(0.5, 0.7, 1.0),
+        );
+    }
+
+    pub fn bottom(&self) -> &color::Color {
+        return &self.bottom;
+    }
+
+    pub fn top(&self) -> &color::Color {
+        return &self.top;
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction: &ray::Vector) -> color::Color {
+        let t = 0.5 * (direction.unit_vector().y() + 1.0);
+        return &self.bottom * (1.0 - t) + &self.top * t;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Environment Map
+//
+// An equirectangular (latitude-longitude) image wrapped around the scene: the columns go around
+// the vertical axis, with the middle of the image straight along -z, and the rows from straight
+// up at the top to straight down at the bottom. The map can be turned around the vertical axis
+// and scaled in brightness.
+//
+// Directions are sampled in proportion to the luminance of the pixels, so that a small bright sun
+// gets found by light sampling rather than by the odd scattered ray.
+pub struct EnvironmentMap {
+    path: String,
+    image: image_input::Image,
+    distribution: distribution::Distribution2D,
+    rotation: RayTracingFloat, // in degrees, turning the map counterclockwise seen from above
+    intensity: RayTracingFloat,
+}
+
+impl EnvironmentMap {
+    pub fn load(
+        path: &str,
+        rotation: RayTracingFloat,
+        intensity: RayTracingFloat,
+    ) -> Result<Self, String> {
+        let image = image_input::read_image(path)?;
+
+        // Lookups blend neighboring pixels, so every pixel is given the brightest luminance around
+        // it. Otherwise, the light a bright pixel bleeds into its dim neighbors would be sampled
+        // with their low density, and show up as fireflies. Rows near the poles are squeezed into
+        // less solid angle, by the sine of the polar angle.
+        let luminance = image
+            .pixels
+            .iter()
+            .map(|c| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z())
+            .collect::<Vec<_>>();
+        let mut weights = Vec::with_capacity(image.pixels.len());
+        for y in 0..image.height {
+            let theta = (y as RayTracingFloat + 0.5) / image.height as RayTracingFloat
+                * std::f64::consts::PI;
+            for x in 0..image.width {
+                let mut brightest: RayTracingFloat = 0.0;
+                for ny in y.saturating_sub(1)..(y + 2).min(image.height) {
+                    for dx in [image.width - 1, 0, 1] {
+                        let nx = (x + dx) % image.width;
+                        brightest = brightest.max(luminance[ny * image.width + nx]);
+                    }
+                }
+                weights.push(brightest * theta.sin());
+            }
+        }
+
+        return Ok(Self {
+            path: String::from(path),
+            distribution: distribution::Distribution2D::new(&weights, &image.width, &image.height),
+            image: image,
+            rotation: rotation,
+            intensity: intensity,
+        });
+    }
+
+    pub fn path(&self) -> &str {
+        return &self.path;
+    }
+
+    pub fn rotation(&self) -> RayTracingFloat {
+        return self.rotation;
+    }
+
+    pub fn intensity(&self) -> RayTracingFloat {
+        return self.intensity;
+    }
+
+    // Position in the image of a direction, with u and v in [0, 1] from the left and the top.
+    fn direction_to_uv(&self, direction: &ray::Vector) -> (RayTracingFloat, RayTracingFloat) {
+        let d = direction.unit_vector();
+        let phi = d.x().atan2(-d.z()) + self.rotation.to_radians();
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let u = (0.5 + phi / (2.0 * std::f64::consts::PI)).rem_euclid(1.0);
+        let v = theta / std::f64::consts::PI;
+        return (u, v);
+    }
+
+    // The direction of a position in the image, the inverse of direction_to_uv.
+    fn uv_to_direction(&self, u: &RayTracingFloat, v: &RayTracingFloat) -> ray::Vector {
+        let phi = 2.0 * std::f64::consts::PI * (u - 0.5) - self.rotation.to_radians();
+        let theta = std::f64::consts::PI * v;
+        return ray::Vector::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+    }
+
+    fn pixel(&self, x: &isize, y: &usize) -> &color::Color {
+        // Columns wrap around, rows end at the poles.
+        let x = x.rem_euclid(self.image.width as isize) as usize;
+        let y = (*y).min(self.image.height - 1);
+        return &self.image.pixels[y * self.image.width + x];
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: &ray::Vector) -> color::Color {
+        // Bilinear interpolation between the centers of the four nearest pixels.
+        let (u, v) = self.direction_to_uv(direction);
+        let x = u * self.image.width as RayTracingFloat - 0.5;
+        let y = (v * self.image.height as RayTracingFloat - 0.5).max(0.0);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as usize);
+
+        let top = self.pixel(&x0, &y0) * (1.0 - tx) + self.pixel(&(x0 + 1), &y0) * tx;
+        let bottom =
+            self.pixel(&x0, &(y0 + 1)) * (1.0 - tx) + self.pixel(&(x0 + 1), &(y0 + 1)) * tx;
+        return (top * (1.0 - ty) + bottom * ty) * self.intensity;
+    }
+
+    fn emits(&self) -> bool {
+        return self.intensity > 0.0 && self.image.pixels.iter().any(|c| c.length_squared() > 0.0);
+    }
+
+    fn sample_direction(&self, u: &(RayTracingFloat, RayTracingFloat)) -> ray::Vector {
+        let ((u, v), _) = self.distribution.sample(u);
+        return self.uv_to_direction(&u, &v);
+    }
+
+    fn pdf(&self, direction: &ray::Vector) -> RayTracingFloat {
+        // The image covers 2π by π radians, and its rows shrink by the sine of the polar angle.
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (std::f64::consts::PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        return self.distribution.pdf(&u, &v)
+            / (2.0 * std::f64::consts::PI * std::f64::consts::PI * sin_theta);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Daylight
+//
+// The clear sky model of Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight"
+// (1999), along with the disk of the sun. The sky follows from the position of the sun and the
+// turbidity, the haziness of the air: about 2 for a very clear sky, 3 for a clear one and up to 10
+// for a hazy one. Below the horizon, the sky just above it is repeated.
+//
+// The sun is as bright as outside the atmosphere, dimmed by Rayleigh scattering and haze along its
+// way through the air, evaluated at one wavelength per color channel. Sky luminance is given in
+// kcd/m² by the model and scaled by SKY_SCALE, the sun the same way.
+pub struct DaylightEnvironment {
+    sun_elevation: RayTracingFloat, // in degrees above the horizon
+    sun_azimuth: RayTracingFloat,   // in degrees, from -z towards +x
+    turbidity: RayTracingFloat,
+    intensity: RayTracingFloat,
+    sun_direction: ray::Vector,
+    sun_radiance: color::Color,
+    zenith: [RayTracingFloat; 3], // x, y chromaticity and luminance Y straight up
+    perez: [[RayTracingFloat; 5]; 3], // coefficients A to E of x, y and Y
+    perez_sun: [RayTracingFloat; 3], // distribution of x, y and Y at the zenith
+}
+
+// Radiance of 1 kcd/m² of luminance, chosen so that a white surface in the sun comes out at about
+// 1, like under the lights of the other scenes.
+const SKY_SCALE: RayTracingFloat = 0.05;
+// Luminance of the sun outside the atmosphere, in kcd/m².
+const SUN_LUMINANCE: RayTracingFloat = 1.6e6;
+// Angular radius of the sun's disk, in degrees.
+const SUN_RADIUS: RayTracingFloat = 0.2667;
+// Share of the light samples aimed at the sun rather than the whole sky.
+const SUN_SAMPLING_PROBABILITY: RayTracingFloat = 0.5;
+// Wavelengths, in micrometers, standing in for the red, green and blue channels.
+const CHANNEL_WAVELENGTHS: [RayTracingFloat; 3] = [0.680, 0.550, 0.440];
+
+impl DaylightEnvironment {
+    pub fn new(
+        sun_elevation: RayTracingFloat,
+        sun_azimuth: RayTracingFloat,
+        turbidity: RayTracingFloat,
+        intensity: RayTracingFloat,
+    ) -> Self {
+        let t = turbidity;
+        let elevation = sun_elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = sun_azimuth.to_radians();
+        let sun_direction = ray::Vector::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let theta_sun = std::f64::consts::FRAC_PI_2 - elevation;
+
+        // Zenith luminance and chromaticity.
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f64::consts::PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [RayTracingFloat; 4]| {
+            c[0] * theta_sun.powi(3) + c[1] * theta_sun.powi(2) + c[2] * theta_sun + c[3]
+        };
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let perez = [
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+        ];
+        let perez_sun = [
+            Self::perez_function(&perez[0], &1.0, &theta_sun.cos()),
+            Self::perez_function(&perez[1], &1.0, &theta_sun.cos()),
+            Self::perez_function(&perez[2], &1.0, &theta_sun.cos()),
+        ];
+
+        // Extinction along the sun's path through the atmosphere, with the relative air mass of
+        // Kasten and Young (1989) and Ångström's formula for the haze.
+        let theta_sun_degrees = theta_sun.to_degrees();
+        let air_mass =
+            1.0 / (theta_sun.cos() + 0.50572 * (96.07995 - theta_sun_degrees).powf(-1.6364));
+        let beta = 0.04608 * t - 0.04586;
+        let mut transmittance = color::Color::zero();
+        for (c, wavelength) in CHANNEL_WAVELENGTHS.iter().enumerate() {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            transmittance[c] = (-air_mass * (rayleigh + aerosol)).exp();
+        }
+
+        return Self {
+            sun_elevation: sun_elevation,
+            sun_azimuth: sun_azimuth,
+            turbidity: turbidity,
+            intensity: intensity,
+            sun_direction: sun_direction,
+            sun_radiance: transmittance * (SUN_LUMINANCE * SKY_SCALE),
+            zenith: [zenith_x, zenith_y, zenith_luminance],
+            perez: perez,
+            perez_sun: perez_sun,
+        };
+    }
+
+    pub fn sun_elevation(&self) -> RayTracingFloat {
+        return self.sun_elevation;
+    }
+
+    pub fn sun_azimuth(&self) -> RayTracingFloat {
+        return self.sun_azimuth;
+    }
+
+    pub fn turbidity(&self) -> RayTracingFloat {
+        return self.turbidity;
+    }
+
+    pub fn intensity(&self) -> RayTracingFloat {
+        return self.intensity;
+    }
+
+    // Relative distribution of the sky, for a direction at the given cosines of the angles to the
+    // zenith and to the sun.
+    fn perez_function(
+        coefficients: &[RayTracingFloat; 5],
+        cos_theta: &RayTracingFloat,
+        cos_gamma: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        let [a, b, c, d, e] = *coefficients;
+        let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+        return (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma);
+    }
+
+    fn sky(&self, direction: &ray::Vector) -> color::Color {
+        // Directions below the horizon get the sky right above it.
+        let mut d = ray::Vector::new(direction.x(), direction.y().max(0.0), direction.z());
+        if d.length_squared() == 0.0 {
+            d = ray::Vector::new(1.0, 0.0, 0.0);
+        }
+        let d = d.unit_vector();
+        let cos_theta = d.y().max(1e-3);
+        let cos_gamma = vec3::dot(&d, &self.sun_direction);
+
+        let mut xyy = [0.0; 3];
+        for (i, value) in xyy.iter_mut().enumerate() {
+            *value = self.zenith[i] * Self::perez_function(&self.perez[i], &cos_theta, &cos_gamma)
+                / self.perez_sun[i];
+        }
+        let [x, y, luminance] = xyy;
+
+        // From xyY to XYZ, and on to linear Rec.709.
+        let luminance = luminance * SKY_SCALE;
+        let cie_x = x / y * luminance;
+        let cie_z = (1.0 - x - y) / y * luminance;
+        return color::Color::new(
+            (3.2406 * cie_x - 1.5372 * luminance - 0.4986 * cie_z).max(0.0),
+            (-0.9689 * cie_x + 1.8758 * luminance + 0.0415 * cie_z).max(0.0),
+            (0.0557 * cie_x - 0.2040 * luminance + 1.0570 * cie_z).max(0.0),
+        );
+    }
+
+    fn sun_cos_radius() -> RayTracingFloat {
+        return SUN_RADIUS.to_radians().cos();
+    }
+}
+
+impl Environment for DaylightEnvironment {
+    fn radiance(&self, direction: &ray::Vector) -> color::Color {
+        let mut radiance = self.sky(direction);
+        let cos_gamma = vec3::dot(&direction.unit_vector(), &self.sun_direction);
+        if cos_gamma >= Self::sun_cos_radius() {
+            radiance += &self.sun_radiance;
+        }
+        return radiance * self.intensity;
+    }
+
+    fn emits(&self) -> bool {
+        return self.intensity > 0.0;
+    }
+
+    // Samples either the disk of the sun or, like the default, the whole sphere of directions.
+    fn sample_direction(&self, u: &(RayTracingFloat, RayTracingFloat)) -> ray::Vector {
+        if u.0 >= SUN_SAMPLING_PROBABILITY {
+            let u0 = (u.0 - SUN_SAMPLING_PROBABILITY) / (1.0 - SUN_SAMPLING_PROBABILITY);
+            return ray::Vector::sample_unit_vector(&(u0, u.1));
+        }
+
+        let u0 = u.0 / SUN_SAMPLING_PROBABILITY;
+        let z = 1.0 - u0 * (1.0 - Self::sun_cos_radius());
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u.1;
+        let uvw = onb::OrthonormalBasis::build_from_w(&self.sun_direction);
+        return uvw.local(&ray::Vector::new(r * phi.cos(), r * phi.sin(), z));
+    }
+
+    fn pdf(&self, direction: &ray::Vector) -> RayTracingFloat {
+        let sky_pdf = 1.0 / (4.0 * std::f64::consts::PI);
+        let cos_gamma = vec3::dot(&direction.unit_vector(), &self.sun_direction);
+        let sun_pdf = if cos_gamma >= Self::sun_cos_radius() {
+            1.0 / (2.0 * std::f64::consts::PI * (1.0 - Self::sun_cos_radius()))
+        } else {
+            0.0
+        };
+        return SUN_SAMPLING_PROBABILITY * sun_pdf + (1.0 - SUN_SAMPLING_PROBABILITY) * sky_pdf;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Built-in Environments
+//
+// Settings of the built-in environments, and of environment maps given on the command line.
+pub struct EnvironmentSettings {
+    pub rotation: RayTracingFloat, // of environment maps
+    pub intensity: RayTracingFloat,
+    pub sun_elevation: RayTracingFloat,
+    pub sun_azimuth: RayTracingFloat,
+    pub turbidity: RayTracingFloat,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        return Self {
+            rotation: 0.0,
+            intensity: 1.0,
+            sun_elevation: 45.0,
+            sun_azimuth: 0.0,
+            turbidity: 3.0,
+        };
+    }
+}
+
+pub struct BuiltinEnvironment {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub build: fn(&EnvironmentSettings) -> std::sync::Arc<dyn Environment>,
+}
+
+pub const BUILTIN_ENVIRONMENTS: &[BuiltinEnvironment] = &[
+    BuiltinEnvironment {
+        name: "black",
+        description: "no light from the surroundings",
+        build: |_settings| std::sync::Arc::new(ConstantEnvironment::new(color::Color::zero())),
+    },
+    BuiltinEnvironment {
+        name: "white",
+        description: "uniform white light from every direction",
+        build: |settings| {
+            std::sync::Arc::new(ConstantEnvironment::new(
+                color::Color::new(1.0, 1.0, 1.0) * settings.intensity,
+            ))
+        },
+    },
+    BuiltinEnvironment {
+        name: "sky",
+        description: "white to blue gradient of \"Ray Tracing in One Weekend\"",
+        build: |settings| {
+            let sky = GradientEnvironment::sky();
+            std::sync::Arc::new(GradientEnvironment::new(
+                sky.bottom() * settings.intensity,
+                sky.top() * settings.intensity,
+            ))
+        },
+    },
+    BuiltinEnvironment {
+        name: "daylight",
+        description: "physically based sky and sun, set with --sun-elevation, --sun-azimuth and \
+                      --turbidity",
+        build: |settings| {
+            std::sync::Arc::new(DaylightEnvironment::new(
+                settings.sun_elevation,
+                settings.sun_azimuth,
+                settings.turbidity,
+                settings.intensity,
+            ))
+        },
+    },
+];
+
+pub fn find_builtin(name: &str) -> Option<&'static BuiltinEnvironment> {
+    return BUILTIN_ENVIRONMENTS
+        .iter()
+        .find(|environment| environment.name == name);
+}
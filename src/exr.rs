@@ -0,0 +1,198 @@
+// Minimal OpenEXR encoder: a single part scanline image made of any number of named 32-bit float
+// channels, stored with the lossless RLE compression.
+//
+// See "The OpenEXR File Layout" (https://openexr.com/en/latest/OpenEXRFileLayout.html).
+
+// The "compression" attribute value of RLE.
+const RLE_COMPRESSION: u8 = 1;
+
+// One channel of the image, with one value per pixel stored row by row from the top.
+pub struct Channel<'a> {
+    pub name: &'a str,
+    pub data: &'a [f32],
+}
+
+impl<'a> Channel<'a> {
+    pub fn new(name: &'a str, data: &'a [f32]) -> Self {
+        return Channel {
+            name: name,
+            data: data,
+        };
+    }
+}
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: [u8; 4] = [2, 0, 0, 0]; // version 2, single part scanline file
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+fn push_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_attribute(out: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(type_name.as_bytes());
+    out.push(0);
+    push_i32(out, value.len() as i32);
+    out.extend_from_slice(value);
+}
+
+fn box2i(xmax: i32, ymax: i32) -> Vec<u8> {
+    let mut value = Vec::new();
+    for v in [0, 0, xmax, ymax] {
+        push_i32(&mut value, v);
+    }
+    return value;
+}
+
+fn header(image_width: &usize, image_height: &usize, channels: &[&Channel]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION);
+
+    let mut channel_list = Vec::new();
+    for channel in channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        push_i32(&mut channel_list, PIXEL_TYPE_FLOAT);
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved bytes
+        push_i32(&mut channel_list, 1); // x sampling
+        push_i32(&mut channel_list, 1); // y sampling
+    }
+    channel_list.push(0);
+
+    let one = 1.0f32.to_le_bytes();
+    let window = box2i(*image_width as i32 - 1, *image_height as i32 - 1);
+    push_attribute(&mut out, "channels", "chlist", &channel_list);
+    push_attribute(&mut out, "compression", "compression", &[RLE_COMPRESSION]);
+    push_attribute(&mut out, "dataWindow", "box2i", &window);
+    push_attribute(&mut out, "displayWindow", "box2i", &window);
+    push_attribute(&mut out, "lineOrder", "lineOrder", &[0]); // increasing y
+    push_attribute(&mut out, "pixelAspectRatio", "float", &one);
+    push_attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    push_attribute(&mut out, "screenWindowWidth", "float", &one);
+    out.push(0);
+    return out;
+}
+
+// Prepares data for run-length encoding as OpenEXR does: the bytes are split into two halves
+// holding the even and the odd bytes, then replaced by the difference to their predecessor.
+fn reorder_and_predict(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(raw.len());
+    data.extend(raw.iter().step_by(2).copied());
+    data.extend(raw.iter().skip(1).step_by(2).copied());
+
+    // The first byte is kept as is.
+    let mut previous = match data.first() {
+        Some(first) => *first,
+        None => return data,
+    };
+    for byte in data.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+    return data;
+}
+
+// Runs of at least three identical bytes become (count - 1, byte), anything else is copied as
+// (-count, bytes...).
+fn rle_compress(data: &[u8]) -> Vec<u8> {
+    const MIN_RUN_LENGTH: usize = 3;
+    const MAX_RUN_LENGTH: usize = 127;
+
+    let mut out = Vec::new();
+    let mut run_start = 0;
+    let mut run_end = 1;
+    while run_start < data.len() {
+        while run_end < data.len()
+            && data[run_start] == data[run_end]
+            && run_end - run_start - 1 < MAX_RUN_LENGTH
+        {
+            run_end += 1;
+        }
+
+        if run_end - run_start >= MIN_RUN_LENGTH {
+            out.push((run_end - run_start - 1) as u8);
+            out.push(data[run_start]);
+            run_start = run_end;
+        } else {
+            // Extend the literal up to the next run of three identical bytes.
+            while run_end < data.len()
+                && (run_end + 2 >= data.len()
+                    || data[run_end] != data[run_end + 1]
+                    || data[run_end + 1] != data[run_end + 2])
+                && run_end - run_start < MAX_RUN_LENGTH
+            {
+                run_end += 1;
+            }
+            out.push((-((run_end - run_start) as i32)) as u8);
+            out.extend_from_slice(&data[run_start..run_end]);
+            run_start = run_end;
+        }
+        run_end += 1;
+    }
+    return out;
+}
+
+pub fn encode(
+    image_width: &usize,
+    image_height: &usize,
+    channels: &[Channel],
+) -> Result<Vec<u8>, String> {
+    if channels.is_empty() {
+        return Err(String::from("an EXR image needs at least one channel"));
+    }
+    for channel in channels {
+        if channel.data.len() != image_width * image_height {
+            return Err(format!(
+                "EXR channel '{}' has {} values, expected {}",
+                channel.name,
+                channel.data.len(),
+                image_width * image_height
+            ));
+        }
+    }
+
+    // Readers expect the channels sorted by name, both in the header and in the pixel data.
+    let mut channels = channels.iter().collect::<Vec<_>>();
+    channels.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    if channels.windows(2).any(|pair| pair[0].name == pair[1].name) {
+        return Err(String::from("EXR channel names must be unique"));
+    }
+
+    let mut out = header(image_width, image_height, &channels);
+
+    // Every chunk holds a single scanline. The offset table pointing at the chunks is filled in
+    // once their sizes are known.
+    let table_start = out.len();
+    out.resize(table_start + 8 * image_height, 0);
+
+    let mut raw = Vec::with_capacity(4 * image_width * channels.len());
+    for y in 0..*image_height {
+        raw.clear();
+        for channel in channels.iter() {
+            for value in &channel.data[y * image_width..(y + 1) * image_width] {
+                raw.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        // Data that would not get any smaller is stored as is, which readers detect from the
+        // chunk size.
+        let compressed = rle_compress(&reorder_and_predict(&raw));
+        let data = if compressed.len() < raw.len() {
+            &compressed
+        } else {
+            &raw
+        };
+
+        let offset = out.len() as u64;
+        out[table_start + 8 * y..table_start + 8 * (y + 1)].copy_from_slice(&offset.to_le_bytes());
+        push_i32(&mut out, y as i32);
+        push_i32(&mut out, data.len() as i32);
+        out.extend_from_slice(data);
+    }
+
+    return Ok(out);
+}
@@ -0,0 +1,298 @@
+// Pixel reconstruction filters, which decide how much the samples around a pixel contribute to it.
+// All filters here are separable: the weight of an offset (x, y) from the pixel center is the
+// product of a one dimensional filter applied to x and to y.
+//
+// Rather than spreading every sample over the pixels around it, the offsets of camera rays are
+// drawn with a density following the filter (filter importance sampling, Ernst et al. 2006), which
+// keeps the pixels of an image independent of each other. Filters with negative lobes still get
+// them by giving the samples that land there a negative weight.
+use super::utils::RayTracingFloat;
+
+pub trait Filter: Send + Sync {
+    // Offsets from the pixel center beyond the radius have a weight of zero.
+    fn radius(&self) -> RayTracingFloat;
+
+    fn evaluate(&self, x: &RayTracingFloat) -> RayTracingFloat;
+}
+
+//
+// Box
+//
+// Every sample within the radius counts the same, which with a radius of half a pixel simply
+// averages the samples taken inside the pixel.
+pub struct BoxFilter {
+    radius: RayTracingFloat,
+}
+
+impl BoxFilter {
+    pub fn new(radius: RayTracingFloat) -> Self {
+        return Self { radius: radius };
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> RayTracingFloat {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return if x.abs() <= self.radius { 1.0 } else { 0.0 };
+    }
+}
+
+//
+// Tent
+//
+pub struct TentFilter {
+    radius: RayTracingFloat,
+}
+
+impl TentFilter {
+    pub fn new(radius: RayTracingFloat) -> Self {
+        return Self { radius: radius };
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> RayTracingFloat {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return (self.radius - x.abs()).max(0.0);
+    }
+}
+
+//
+// Gaussian
+//
+// A Gaussian with a standard deviation of a third of the radius, shifted down so that it reaches
+// zero at the radius.
+pub struct GaussianFilter {
+    radius: RayTracingFloat,
+    sigma: RayTracingFloat,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: RayTracingFloat) -> Self {
+        return Self {
+            radius: radius,
+            sigma: radius / 3.0,
+        };
+    }
+
+    fn gaussian(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return (-x * x / (2.0 * self.sigma * self.sigma)).exp();
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> RayTracingFloat {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return (self.gaussian(x) - self.gaussian(&self.radius)).max(0.0);
+    }
+}
+
+//
+// Mitchell-Netravali
+//
+// The cubic from Mitchell and Netravali, "Reconstruction Filters in Computer Graphics" (1988),
+// with the B = C = 1/3 they recommend, stretched to the radius.
+pub struct MitchellFilter {
+    radius: RayTracingFloat,
+    b: RayTracingFloat,
+    c: RayTracingFloat,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: RayTracingFloat) -> Self {
+        return Self {
+            radius: radius,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        };
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> RayTracingFloat {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        // The cubic is defined over [-2, 2].
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let value = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+        return value / 6.0;
+    }
+}
+
+//
+// Lanczos
+//
+// A sinc windowed by a wider sinc, which reaches zero at the radius.
+pub struct LanczosFilter {
+    radius: RayTracingFloat,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: RayTracingFloat) -> Self {
+        return Self { radius: radius };
+    }
+
+    fn sinc(x: &RayTracingFloat) -> RayTracingFloat {
+        if x.abs() < 1e-5 {
+            return 1.0;
+        }
+        let px = std::f64::consts::PI * x;
+        return px.sin() / px;
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> RayTracingFloat {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        if x.abs() > self.radius {
+            return 0.0;
+        }
+        return Self::sinc(x) * Self::sinc(&(x / self.radius));
+    }
+}
+
+//
+// Filter Sampler
+//
+// Draws offsets from the pixel center with a density proportional to the absolute value of the
+// filter, tabulated at a fixed resolution.
+pub struct FilterSampler {
+    filter: std::sync::Arc<dyn Filter>,
+    cdf: Vec<RayTracingFloat>, // cdf[i] is the probability of the offset falling below bin i
+    integral: RayTracingFloat,
+}
+
+// Resolution of the tabulated filter, in bins per pixel.
+const BINS_PER_PIXEL: RayTracingFloat = 64.0;
+
+impl FilterSampler {
+    pub fn new(filter: std::sync::Arc<dyn Filter>) -> Self {
+        let radius = filter.radius();
+        let bins = ((2.0 * radius * BINS_PER_PIXEL).ceil() as usize).max(1);
+        let bin_width = 2.0 * radius / bins as RayTracingFloat;
+
+        let mut cdf = Vec::with_capacity(bins + 1);
+        cdf.push(0.0);
+        let mut absolute_integral = 0.0;
+        let mut integral = 0.0;
+        for i in 0..bins {
+            let x = -radius + (i as RayTracingFloat + 0.5) * bin_width;
+            let value = filter.evaluate(&x);
+            absolute_integral += value.abs() * bin_width;
+            integral += value * bin_width;
+            cdf.push(absolute_integral);
+        }
+        for c in cdf.iter_mut() {
+            *c /= absolute_integral;
+        }
+
+        return Self {
+            filter: filter,
+            cdf: cdf,
+            integral: integral,
+        };
+    }
+
+    // Maps a sample dimension to an offset from the pixel center, along with the weight the
+    // sample gets along that axis. Weights average to 1 over many samples.
+    pub fn sample(&self, u: &RayTracingFloat) -> (RayTracingFloat, RayTracingFloat) {
+        let radius = self.filter.radius();
+        let bins = self.cdf.len() - 1;
+        let bin_width = 2.0 * radius / bins as RayTracingFloat;
+
+        // The last bin whose cdf is not above u; bins without any weight are never picked.
+        let bin = (self.cdf.partition_point(|c| c <= u) - 1).min(bins - 1);
+        let bin_probability = self.cdf[bin + 1] - self.cdf[bin];
+        let within_bin = if bin_probability > 0.0 {
+            (u - self.cdf[bin]) / bin_probability
+        } else {
+            0.5
+        };
+        let x = -radius + (bin as RayTracingFloat + within_bin) * bin_width;
+
+        // Weight: filter value over the density it was sampled with, normalized by the integral
+        // of the filter. For filters without negative lobes, this is about 1 everywhere.
+        let pdf = bin_probability / bin_width;
+        let weight = if pdf > 0.0 {
+            self.filter.evaluate(&x) / pdf / self.integral
+        } else {
+            0.0
+        };
+        return (x, weight);
+    }
+}
+
+//
+// Filter Types
+//
+pub struct FilterType {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default_radius: RayTracingFloat, // in pixels
+    pub build: fn(&RayTracingFloat) -> std::sync::Arc<dyn Filter>,
+}
+
+pub const FILTERS: &[FilterType] = &[
+    FilterType {
+        name: "box",
+        description: "equal weights, averages the samples in the pixel [default radius: 0.5]",
+        default_radius: 0.5,
+        build: |radius| std::sync::Arc::new(BoxFilter::new(*radius)),
+    },
+    FilterType {
+        name: "tent",
+        description: "weights falling off linearly [default radius: 1]",
+        default_radius: 1.0,
+        build: |radius| std::sync::Arc::new(TentFilter::new(*radius)),
+    },
+    FilterType {
+        name: "gaussian",
+        description: "smooth, slightly soft [default radius: 1.5]",
+        default_radius: 1.5,
+        build: |radius| std::sync::Arc::new(GaussianFilter::new(*radius)),
+    },
+    FilterType {
+        name: "mitchell",
+        description: "Mitchell-Netravali cubic, sharp [default radius: 2]",
+        default_radius: 2.0,
+        build: |radius| std::sync::Arc::new(MitchellFilter::new(*radius)),
+    },
+    FilterType {
+        name: "lanczos",
+        description: "windowed sinc, sharpest but may ring [default radius: 3]",
+        default_radius: 3.0,
+        build: |radius| std::sync::Arc::new(LanczosFilter::new(*radius)),
+    },
+];
+
+pub const DEFAULT_FILTER: &str = "box";
+
+pub fn find_filter(name: &str) -> Option<&'static FilterType> {
+    return FILTERS.iter().find(|filter| filter.name == name);
+}
@@ -1,6 +1,7 @@
 use super::aabb;
 use super::material;
 use super::ray;
+use super::sampler;
 use super::utils;
 use super::utils::RayTracingFloat;
 use super::vec3;
@@ -8,7 +9,7 @@ use super::vec3;
 pub struct HitRecord {
     pub p: ray::Point,
     normal: ray::Vector,
-    pub mat: std::rc::Weak<dyn material::Material>,
+    pub mat: std::sync::Weak<dyn material::Material>,
     pub t: RayTracingFloat,
     pub u: RayTracingFloat,
     pub v: RayTracingFloat,
@@ -20,7 +21,7 @@ impl HitRecord {
         return Self {
             p: ray::Point::zero(),
             normal: ray::Vector::zero(),
-            mat: std::rc::Weak::<material::Metal>::new(),
+            mat: std::sync::Weak::<material::Metal>::new(),
             t: RayTracingFloat::MIN,
             u: RayTracingFloat::MIN,
             v: RayTracingFloat::MIN,
@@ -40,7 +41,7 @@ impl HitRecord {
         return &self.front_face;
     }
 
-    pub fn material(&self) -> std::rc::Rc<dyn material::Material> {
+    pub fn material(&self) -> std::sync::Arc<dyn material::Material> {
         return self.mat.upgrade().unwrap();
     }
 
@@ -54,7 +55,7 @@ impl HitRecord {
     }
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(
         &self,
         r: &ray::Ray,
@@ -63,16 +64,60 @@ pub trait Hittable {
         rec: &mut HitRecord,
     ) -> bool;
 
+    // Like hit, but passes through volumes and only finds surfaces. Shadow rays use it, and take
+    // the light lost in volumes on the way into account with transmittance.
+    fn hit_surface(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.hit(r, t_min, t_max, rec);
+    }
+
+    // Share of the light passing through the volumes in the object along the ray between t_min
+    // and t_max, or an unbiased estimate of it.
+    fn transmittance(
+        &self,
+        _r: &ray::Ray,
+        _t_min: &RayTracingFloat,
+        _t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        return 1.0;
+    }
+
     fn bounding_box(
         &self,
         time0: &RayTracingFloat,
         time1: &RayTracingFloat,
         output_box: &mut aabb::AxisAlignedBoundingBoxes,
     ) -> bool;
+
+    // Probability density, per solid angle, of random() picking the given direction from origin.
+    fn pdf_value(&self, _origin: &ray::Point, _direction: &ray::Vector) -> RayTracingFloat {
+        return 0.0;
+    }
+
+    // Random direction from origin towards a point on the object, for sampling light sources.
+    fn random(&self, _origin: &ray::Point, _sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        return ray::Vector::new(1.0, 0.0, 0.0);
+    }
+
+    // Whether the object emits light and can be sampled with random() as a light source.
+    fn is_light(&self) -> bool {
+        return false;
+    }
+
+    // Adds the light sources among the objects contained in this one to lights.
+    fn collect_lights(&self, _lights: &mut HittableList) {}
+
+    // Gives access to the concrete type, e.g. for writing the scene graph out to a file.
+    fn as_any(&self) -> &dyn std::any::Any;
 }
 
 pub struct HittableList {
-    objects: Vec<std::rc::Rc<dyn Hittable>>,
+    objects: Vec<std::sync::Arc<dyn Hittable>>,
 }
 
 impl HittableList {
@@ -82,38 +127,45 @@ impl HittableList {
         };
     }
 
-    pub fn new(object: std::rc::Rc<dyn Hittable>) -> Self {
+    pub fn new(object: std::sync::Arc<dyn Hittable>) -> Self {
         return Self {
             objects: vec![object],
         };
     }
 
-    pub fn get_objects(&self) -> &Vec<std::rc::Rc<dyn Hittable>> {
+    pub fn get_objects(&self) -> &Vec<std::sync::Arc<dyn Hittable>> {
         return &self.objects;
     }
 
-    pub fn clear(&mut self) {
-        self.objects.clear();
+    pub fn add(&mut self, object: std::sync::Arc<dyn Hittable>) {
+        self.objects.push(object);
     }
 
-    pub fn add(&mut self, object: std::rc::Rc<dyn Hittable>) {
-        self.objects.push(object);
+    // All light sources in the list and the objects it contains.
+    pub fn lights(&self) -> HittableList {
+        let mut lights = HittableList::new_empty();
+        self.collect_lights(&mut lights);
+        return lights;
     }
-}
 
-impl Hittable for HittableList {
-    fn hit(
+    fn closest_hit(
         &self,
         r: &ray::Ray,
         t_min: &RayTracingFloat,
         t_max: &RayTracingFloat,
         rec: &mut HitRecord,
+        surfaces_only: &bool,
     ) -> bool {
         let mut hit_anything = false;
         let mut closest_so_far = t_max.clone();
         for object in &self.objects {
             let mut temp_rec = HitRecord::new();
-            if object.hit(r, t_min, &closest_so_far, &mut temp_rec) {
+            let hit = if *surfaces_only {
+                object.hit_surface(r, t_min, &closest_so_far, &mut temp_rec)
+            } else {
+                object.hit(r, t_min, &closest_so_far, &mut temp_rec)
+            };
+            if hit {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec;
@@ -122,6 +174,66 @@ impl Hittable for HittableList {
 
         return hit_anything;
     }
+}
+
+// Adds an object to lights if it is a light source, or otherwise the lights inside of it.
+fn collect_lights_from(object: &std::sync::Arc<dyn Hittable>, lights: &mut HittableList) {
+    if object.is_light() {
+        let address = std::sync::Arc::as_ptr(object) as *const ();
+        if !lights
+            .objects
+            .iter()
+            .any(|light| std::sync::Arc::as_ptr(light) as *const () == address)
+        {
+            lights.add(object.clone());
+        }
+    } else {
+        object.collect_lights(lights);
+    }
+}
+
+fn lights_in(object: &std::sync::Arc<dyn Hittable>) -> Vec<std::sync::Arc<dyn Hittable>> {
+    let mut lights = HittableList::new_empty();
+    collect_lights_from(object, &mut lights);
+    return lights.objects;
+}
+
+impl Hittable for HittableList {
+    fn hit(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.closest_hit(r, t_min, t_max, rec, &false);
+    }
+
+    fn hit_surface(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.closest_hit(r, t_min, t_max, rec, &true);
+    }
+
+    fn transmittance(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        let mut transmittance = 1.0;
+        for object in &self.objects {
+            if transmittance <= 0.0 {
+                break;
+            }
+            transmittance *= object.transmittance(r, t_min, t_max);
+        }
+        return transmittance;
+    }
 
     fn bounding_box(
         &self,
@@ -152,39 +264,84 @@ impl Hittable for HittableList {
 
         return true;
     }
+
+    // Picks one of the objects with equal probability.
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let weight = 1.0 / self.objects.len() as RayTracingFloat;
+        return self
+            .objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum();
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        let index = (sampler.get_1d() * self.objects.len() as RayTracingFloat) as usize;
+        return self.objects[index.min(self.objects.len() - 1)].random(origin, sampler);
+    }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for object in &self.objects {
+            collect_lights_from(object, lights);
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // Translation transform on another Hittable
 //
 pub struct Translate {
-    ptr: std::rc::Rc<dyn Hittable>,
+    ptr: std::sync::Arc<dyn Hittable>,
     offset: ray::Vector,
 }
 
 impl Translate {
-    pub fn new(p: std::rc::Rc<dyn Hittable>, displacement: ray::Vector) -> Self {
+    pub fn new(p: std::sync::Arc<dyn Hittable>, displacement: ray::Vector) -> Self {
         return Self {
             ptr: p,
             offset: displacement,
         };
     }
-}
 
-impl Hittable for Translate {
-    fn hit(
+    pub fn object(&self) -> &std::sync::Arc<dyn Hittable> {
+        return &self.ptr;
+    }
+
+    pub fn offset(&self) -> &ray::Vector {
+        return &self.offset;
+    }
+
+    fn moved_ray(&self, r: &ray::Ray) -> ray::Ray {
+        return ray::Ray::new_with_key(
+            r.origin() - &self.offset,
+            r.direction().clone(),
+            r.time().clone(),
+            r.random_key().clone(),
+        );
+    }
+
+    fn moved_hit(
         &self,
         r: &ray::Ray,
         t_min: &RayTracingFloat,
         t_max: &RayTracingFloat,
         rec: &mut HitRecord,
+        surfaces_only: &bool,
     ) -> bool {
-        let moved_r = ray::Ray::new(
-            r.origin() - &self.offset,
-            r.direction().clone(),
-            r.time().clone(),
-        );
-        if !self.ptr.hit(&moved_r, t_min, t_max, rec) {
+        let moved_r = self.moved_ray(r);
+        let hit = if *surfaces_only {
+            self.ptr.hit_surface(&moved_r, t_min, t_max, rec)
+        } else {
+            self.ptr.hit(&moved_r, t_min, t_max, rec)
+        };
+        if !hit {
             return false;
         }
 
@@ -194,6 +351,37 @@ impl Hittable for Translate {
 
         return true;
     }
+}
+
+impl Hittable for Translate {
+    fn hit(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.moved_hit(r, t_min, t_max, rec, &false);
+    }
+
+    fn hit_surface(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.moved_hit(r, t_min, t_max, rec, &true);
+    }
+
+    fn transmittance(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        return self.ptr.transmittance(&self.moved_ray(r), t_min, t_max);
+    }
 
     fn bounding_box(
         &self,
@@ -211,13 +399,40 @@ impl Hittable for Translate {
         );
         return true;
     }
+
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        return self.ptr.pdf_value(&(origin - &self.offset), direction);
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        return self.ptr.random(&(origin - &self.offset), sampler);
+    }
+
+    fn is_light(&self) -> bool {
+        return self.ptr.is_light();
+    }
+
+    // Lights inside a group are moved one by one.
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for light in lights_in(&self.ptr) {
+            lights.add(std::sync::Arc::new(Translate::new(
+                light,
+                self.offset.clone(),
+            )));
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // Rotation transform on another Hittable
 //
 pub struct Rotate_Y {
-    ptr: std::rc::Rc<dyn Hittable>,
+    ptr: std::sync::Arc<dyn Hittable>,
+    angle: RayTracingFloat, // in degrees
     sin_theta: RayTracingFloat,
     cos_theta: RayTracingFloat,
     hasbox: bool,
@@ -225,7 +440,7 @@ pub struct Rotate_Y {
 }
 
 impl Rotate_Y {
-    pub fn new(p: std::rc::Rc<dyn Hittable>, angle: RayTracingFloat) -> Self {
+    pub fn new(p: std::sync::Arc<dyn Hittable>, angle: RayTracingFloat) -> Self {
         let radians = angle.to_radians();
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
@@ -268,22 +483,40 @@ impl Rotate_Y {
 
         return Self {
             ptr: p,
+            angle: angle,
             sin_theta: sin_theta,
             cos_theta: cos_theta,
             hasbox: hasbox,
             bbox: aabb::AxisAlignedBoundingBoxes::new(min, max),
         };
     }
-}
 
-impl Hittable for Rotate_Y {
-    fn hit(
-        &self,
-        r: &ray::Ray,
-        t_min: &RayTracingFloat,
-        t_max: &RayTracingFloat,
-        rec: &mut HitRecord,
-    ) -> bool {
+    pub fn object(&self) -> &std::sync::Arc<dyn Hittable> {
+        return &self.ptr;
+    }
+
+    pub fn angle(&self) -> &RayTracingFloat {
+        return &self.angle;
+    }
+
+    // Rotates a point or direction from world space into the space of the rotated object.
+    fn to_object(&self, v: &ray::Vector) -> ray::Vector {
+        return ray::Vector::new(
+            self.cos_theta * v[0] - self.sin_theta * v[2],
+            v[1],
+            self.sin_theta * v[0] + self.cos_theta * v[2],
+        );
+    }
+
+    fn to_world(&self, v: &ray::Vector) -> ray::Vector {
+        return ray::Vector::new(
+            self.cos_theta * v[0] + self.sin_theta * v[2],
+            v[1],
+            -self.sin_theta * v[0] + self.cos_theta * v[2],
+        );
+    }
+
+    fn rotated_ray(&self, r: &ray::Ray) -> ray::Ray {
         let mut origin = r.origin().clone();
         let mut direction = r.direction().clone();
 
@@ -293,9 +526,24 @@ impl Hittable for Rotate_Y {
         direction[0] = self.cos_theta * r.direction()[0] - self.sin_theta * r.direction()[2];
         direction[2] = self.sin_theta * r.direction()[0] + self.cos_theta * r.direction()[2];
 
-        let rotated_r = ray::Ray::new(origin, direction, r.time().clone());
+        return ray::Ray::new_with_key(origin, direction, r.time().clone(), r.random_key().clone());
+    }
 
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
+    fn rotated_hit(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+        surfaces_only: &bool,
+    ) -> bool {
+        let rotated_r = self.rotated_ray(r);
+        let hit = if *surfaces_only {
+            self.ptr.hit_surface(&rotated_r, t_min, t_max, rec)
+        } else {
+            self.ptr.hit(&rotated_r, t_min, t_max, rec)
+        };
+        if !hit {
             return false;
         }
 
@@ -313,6 +561,37 @@ impl Hittable for Rotate_Y {
 
         return true;
     }
+}
+
+impl Hittable for Rotate_Y {
+    fn hit(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.rotated_hit(r, t_min, t_max, rec, &false);
+    }
+
+    fn hit_surface(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        return self.rotated_hit(r, t_min, t_max, rec, &true);
+    }
+
+    fn transmittance(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        return self.ptr.transmittance(&self.rotated_ray(r), t_min, t_max);
+    }
 
     fn bounding_box(
         &self,
@@ -323,14 +602,39 @@ impl Hittable for Rotate_Y {
         *output_box = self.bbox.clone();
         return self.hasbox;
     }
+
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        return self
+            .ptr
+            .pdf_value(&self.to_object(origin), &self.to_object(direction));
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        return self.to_world(&self.ptr.random(&self.to_object(origin), sampler));
+    }
+
+    fn is_light(&self) -> bool {
+        return self.ptr.is_light();
+    }
+
+    // Lights inside a group are rotated one by one.
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for light in lights_in(&self.ptr) {
+            lights.add(std::sync::Arc::new(Rotate_Y::new(light, self.angle)));
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // Bounding Volume Hierarchies Node
 //
 pub struct BVH_Node {
-    left: std::rc::Rc<dyn Hittable>,
-    right: std::rc::Rc<dyn Hittable>,
+    left: std::sync::Arc<dyn Hittable>,
+    right: std::sync::Arc<dyn Hittable>,
     bounding_box: aabb::AxisAlignedBoundingBoxes,
 }
 
@@ -345,7 +649,7 @@ impl BVH_Node {
     }
 
     pub fn new(
-        src_objects: &Vec<std::rc::Rc<dyn Hittable>>,
+        src_objects: &[std::sync::Arc<dyn Hittable>],
         start: &usize,
         end: &usize,
         time0: &RayTracingFloat,
@@ -375,10 +679,10 @@ impl BVH_Node {
 
             let mid = objects.len() / 2;
             (
-                std::rc::Rc::new(BVH_Node::new(&objects, &0, &mid, time0, time1))
-                    as std::rc::Rc<dyn Hittable>,
-                std::rc::Rc::new(BVH_Node::new(&objects, &mid, &objects.len(), time0, time1))
-                    as std::rc::Rc<dyn Hittable>,
+                std::sync::Arc::new(BVH_Node::new(&objects, &0, &mid, time0, time1))
+                    as std::sync::Arc<dyn Hittable>,
+                std::sync::Arc::new(BVH_Node::new(&objects, &mid, &objects.len(), time0, time1))
+                    as std::sync::Arc<dyn Hittable>,
             )
         };
 
@@ -398,6 +702,14 @@ impl BVH_Node {
             bounding_box: aabb::surrounding_box(&box_left, &box_right),
         };
     }
+
+    pub fn left(&self) -> &std::sync::Arc<dyn Hittable> {
+        return &self.left;
+    }
+
+    pub fn right(&self) -> &std::sync::Arc<dyn Hittable> {
+        return &self.right;
+    }
 }
 
 impl Hittable for BVH_Node {
@@ -420,6 +732,42 @@ impl Hittable for BVH_Node {
         return hit_left || hit_right;
     }
 
+    fn hit_surface(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut HitRecord,
+    ) -> bool {
+        if !self.bounding_box.hit(r, t_min, t_max) {
+            return false;
+        }
+
+        let hit_left = self.left.hit_surface(r, t_min, t_max, rec);
+        let right_t_max = if hit_left { rec.t } else { *t_max };
+        let hit_right = self.right.hit_surface(r, t_min, &right_t_max, rec);
+
+        return hit_left || hit_right;
+    }
+
+    fn transmittance(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        if !self.bounding_box.hit(r, t_min, t_max) {
+            return 1.0;
+        }
+
+        let transmittance = self.left.transmittance(r, t_min, t_max);
+        // A node built from a single object holds it as both of its children.
+        if transmittance <= 0.0 || std::sync::Arc::ptr_eq(&self.left, &self.right) {
+            return transmittance;
+        }
+        return transmittance * self.right.transmittance(r, t_min, t_max);
+    }
+
     fn bounding_box(
         &self,
         _time0: &RayTracingFloat,
@@ -429,4 +777,13 @@ impl Hittable for BVH_Node {
         *output_box = self.bounding_box.clone();
         return true;
     }
+
+    fn collect_lights(&self, lights: &mut HittableList) {
+        collect_lights_from(&self.left, lights);
+        collect_lights_from(&self.right, lights);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
@@ -2,13 +2,14 @@ use super::aabb;
 use super::hittable;
 use super::material;
 use super::ray;
+use super::sampler;
 use super::utils::RayTracingFloat;
 
 //
 // XY Rect
 //
 pub struct XY_Rect {
-    mp: std::rc::Rc<dyn material::Material>,
+    mp: std::sync::Arc<dyn material::Material>,
     x0: RayTracingFloat,
     x1: RayTracingFloat,
     y0: RayTracingFloat,
@@ -23,7 +24,7 @@ impl XY_Rect {
         _y0: RayTracingFloat,
         _y1: RayTracingFloat,
         _k: RayTracingFloat,
-        mat: std::rc::Rc<dyn material::Material>,
+        mat: std::sync::Arc<dyn material::Material>,
     ) -> Self {
         return Self {
             mp: mat,
@@ -34,6 +35,30 @@ impl XY_Rect {
             k: _k,
         };
     }
+
+    pub fn x0(&self) -> &RayTracingFloat {
+        return &self.x0;
+    }
+
+    pub fn x1(&self) -> &RayTracingFloat {
+        return &self.x1;
+    }
+
+    pub fn y0(&self) -> &RayTracingFloat {
+        return &self.y0;
+    }
+
+    pub fn y1(&self) -> &RayTracingFloat {
+        return &self.y1;
+    }
+
+    pub fn k(&self) -> &RayTracingFloat {
+        return &self.k;
+    }
+
+    pub fn material(&self) -> &std::sync::Arc<dyn material::Material> {
+        return &self.mp;
+    }
 }
 
 impl hittable::Hittable for XY_Rect {
@@ -59,7 +84,7 @@ impl hittable::Hittable for XY_Rect {
         rec.v = (y - self.y0) / (self.y1 - self.y0);
         rec.t = t;
         rec.set_face_normal(r, &ray::Vector::new(0.0, 0.0, 1.0));
-        rec.mat = std::rc::Rc::downgrade(&self.mp);
+        rec.mat = std::sync::Arc::downgrade(&self.mp);
         rec.p = r.at(&t);
 
         return true;
@@ -79,13 +104,49 @@ impl hittable::Hittable for XY_Rect {
         );
         return true;
     }
+
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        let mut rec = hittable::HitRecord::new();
+        if !self.hit(
+            &ray::Ray::new(origin.clone(), direction.clone(), 0.0),
+            &0.001,
+            &RayTracingFloat::INFINITY,
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        // Convert the density over the area of the rectangle to one over solid angle.
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = (direction.z() / direction.length()).abs();
+        return distance_squared / (cosine * area);
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        let (s, t) = sampler.get_2d();
+        let random_point = ray::Point::new(
+            self.x0 + s * (self.x1 - self.x0),
+            self.y0 + t * (self.y1 - self.y0),
+            self.k,
+        );
+        return random_point - origin;
+    }
+
+    fn is_light(&self) -> bool {
+        return self.mp.emits();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // XZ Rect
 //
 pub struct XZ_Rect {
-    mp: std::rc::Rc<dyn material::Material>,
+    mp: std::sync::Arc<dyn material::Material>,
     x0: RayTracingFloat,
     x1: RayTracingFloat,
     z0: RayTracingFloat,
@@ -100,7 +161,7 @@ impl XZ_Rect {
         _z0: RayTracingFloat,
         _z1: RayTracingFloat,
         _k: RayTracingFloat,
-        mat: std::rc::Rc<dyn material::Material>,
+        mat: std::sync::Arc<dyn material::Material>,
     ) -> Self {
         return Self {
             mp: mat,
@@ -111,6 +172,30 @@ impl XZ_Rect {
             k: _k,
         };
     }
+
+    pub fn x0(&self) -> &RayTracingFloat {
+        return &self.x0;
+    }
+
+    pub fn x1(&self) -> &RayTracingFloat {
+        return &self.x1;
+    }
+
+    pub fn z0(&self) -> &RayTracingFloat {
+        return &self.z0;
+    }
+
+    pub fn z1(&self) -> &RayTracingFloat {
+        return &self.z1;
+    }
+
+    pub fn k(&self) -> &RayTracingFloat {
+        return &self.k;
+    }
+
+    pub fn material(&self) -> &std::sync::Arc<dyn material::Material> {
+        return &self.mp;
+    }
 }
 
 impl hittable::Hittable for XZ_Rect {
@@ -136,7 +221,7 @@ impl hittable::Hittable for XZ_Rect {
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
         rec.set_face_normal(r, &ray::Vector::new(0.0, 1.0, 0.0));
-        rec.mat = std::rc::Rc::downgrade(&self.mp);
+        rec.mat = std::sync::Arc::downgrade(&self.mp);
         rec.p = r.at(&t);
 
         return true;
@@ -156,13 +241,49 @@ impl hittable::Hittable for XZ_Rect {
         );
         return true;
     }
+
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        let mut rec = hittable::HitRecord::new();
+        if !self.hit(
+            &ray::Ray::new(origin.clone(), direction.clone(), 0.0),
+            &0.001,
+            &RayTracingFloat::INFINITY,
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        // Convert the density over the area of the rectangle to one over solid angle.
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = (direction.y() / direction.length()).abs();
+        return distance_squared / (cosine * area);
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        let (s, t) = sampler.get_2d();
+        let random_point = ray::Point::new(
+            self.x0 + s * (self.x1 - self.x0),
+            self.k,
+            self.z0 + t * (self.z1 - self.z0),
+        );
+        return random_point - origin;
+    }
+
+    fn is_light(&self) -> bool {
+        return self.mp.emits();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // YZ Rect
 //
 pub struct YZ_Rect {
-    mp: std::rc::Rc<dyn material::Material>,
+    mp: std::sync::Arc<dyn material::Material>,
     y0: RayTracingFloat,
     y1: RayTracingFloat,
     z0: RayTracingFloat,
@@ -177,7 +298,7 @@ impl YZ_Rect {
         _z0: RayTracingFloat,
         _z1: RayTracingFloat,
         _k: RayTracingFloat,
-        mat: std::rc::Rc<dyn material::Material>,
+        mat: std::sync::Arc<dyn material::Material>,
     ) -> Self {
         return Self {
             mp: mat,
@@ -188,6 +309,30 @@ impl YZ_Rect {
             k: _k,
         };
     }
+
+    pub fn y0(&self) -> &RayTracingFloat {
+        return &self.y0;
+    }
+
+    pub fn y1(&self) -> &RayTracingFloat {
+        return &self.y1;
+    }
+
+    pub fn z0(&self) -> &RayTracingFloat {
+        return &self.z0;
+    }
+
+    pub fn z1(&self) -> &RayTracingFloat {
+        return &self.z1;
+    }
+
+    pub fn k(&self) -> &RayTracingFloat {
+        return &self.k;
+    }
+
+    pub fn material(&self) -> &std::sync::Arc<dyn material::Material> {
+        return &self.mp;
+    }
 }
 
 impl hittable::Hittable for YZ_Rect {
@@ -213,7 +358,7 @@ impl hittable::Hittable for YZ_Rect {
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
         rec.set_face_normal(r, &ray::Vector::new(1.0, 0.0, 0.0));
-        rec.mat = std::rc::Rc::downgrade(&self.mp);
+        rec.mat = std::sync::Arc::downgrade(&self.mp);
         rec.p = r.at(&t);
 
         return true;
@@ -233,6 +378,42 @@ impl hittable::Hittable for YZ_Rect {
         );
         return true;
     }
+
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        let mut rec = hittable::HitRecord::new();
+        if !self.hit(
+            &ray::Ray::new(origin.clone(), direction.clone(), 0.0),
+            &0.001,
+            &RayTracingFloat::INFINITY,
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        // Convert the density over the area of the rectangle to one over solid angle.
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = (direction.x() / direction.length()).abs();
+        return distance_squared / (cosine * area);
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        let (s, t) = sampler.get_2d();
+        let random_point = ray::Point::new(
+            self.k,
+            self.y0 + s * (self.y1 - self.y0),
+            self.z0 + t * (self.z1 - self.z0),
+        );
+        return random_point - origin;
+    }
+
+    fn is_light(&self) -> bool {
+        return self.mp.emits();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
@@ -242,13 +423,18 @@ pub struct Box {
     box_min: ray::Point,
     box_max: ray::Point,
     sides: hittable::HittableList,
+    mat: std::sync::Arc<dyn material::Material>,
 }
 
 impl Box {
-    pub fn new(p0: ray::Point, p1: ray::Point, ptr: std::rc::Rc<dyn material::Material>) -> Self {
+    pub fn new(
+        p0: ray::Point,
+        p1: ray::Point,
+        ptr: std::sync::Arc<dyn material::Material>,
+    ) -> Self {
         let mut sides = hittable::HittableList::new_empty();
 
-        sides.add(std::rc::Rc::new(XY_Rect::new(
+        sides.add(std::sync::Arc::new(XY_Rect::new(
             p0.x(),
             p1.x(),
             p0.y(),
@@ -256,7 +442,7 @@ impl Box {
             p1.z(),
             ptr.clone(),
         )));
-        sides.add(std::rc::Rc::new(XY_Rect::new(
+        sides.add(std::sync::Arc::new(XY_Rect::new(
             p0.x(),
             p1.x(),
             p0.y(),
@@ -265,7 +451,7 @@ impl Box {
             ptr.clone(),
         )));
 
-        sides.add(std::rc::Rc::new(XZ_Rect::new(
+        sides.add(std::sync::Arc::new(XZ_Rect::new(
             p0.x(),
             p1.x(),
             p0.z(),
@@ -273,7 +459,7 @@ impl Box {
             p1.y(),
             ptr.clone(),
         )));
-        sides.add(std::rc::Rc::new(XZ_Rect::new(
+        sides.add(std::sync::Arc::new(XZ_Rect::new(
             p0.x(),
             p1.x(),
             p0.z(),
@@ -282,7 +468,7 @@ impl Box {
             ptr.clone(),
         )));
 
-        sides.add(std::rc::Rc::new(YZ_Rect::new(
+        sides.add(std::sync::Arc::new(YZ_Rect::new(
             p0.y(),
             p1.y(),
             p0.z(),
@@ -290,21 +476,34 @@ impl Box {
             p1.x(),
             ptr.clone(),
         )));
-        sides.add(std::rc::Rc::new(YZ_Rect::new(
+        sides.add(std::sync::Arc::new(YZ_Rect::new(
             p0.y(),
             p1.y(),
             p0.z(),
             p1.z(),
             p0.x(),
-            ptr,
+            ptr.clone(),
         )));
 
         return Self {
             box_min: p0,
             box_max: p1,
             sides: sides,
+            mat: ptr,
         };
     }
+
+    pub fn min(&self) -> &ray::Point {
+        return &self.box_min;
+    }
+
+    pub fn max(&self) -> &ray::Point {
+        return &self.box_max;
+    }
+
+    pub fn material(&self) -> &std::sync::Arc<dyn material::Material> {
+        return &self.mat;
+    }
 }
 
 impl hittable::Hittable for Box {
@@ -328,4 +527,12 @@ impl hittable::Hittable for Box {
             aabb::AxisAlignedBoundingBoxes::new(self.box_min.clone(), self.box_max.clone());
         return true;
     }
+
+    fn collect_lights(&self, lights: &mut hittable::HittableList) {
+        self.sides.collect_lights(lights);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
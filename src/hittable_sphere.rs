@@ -1,7 +1,9 @@
 use super::aabb;
 use super::hittable;
 use super::material;
+use super::onb;
 use super::ray;
+use super::sampler;
 use super::utils::RayTracingFloat;
 use super::vec3;
 
@@ -11,14 +13,14 @@ use super::vec3;
 pub struct Sphere {
     center: ray::Point,
     radius: RayTracingFloat,
-    mat: std::rc::Rc<dyn material::Material>,
+    mat: std::sync::Arc<dyn material::Material>,
 }
 
 impl Sphere {
     pub fn new(
         cen: ray::Point,
         r: RayTracingFloat,
-        m: std::rc::Rc<dyn material::Material>,
+        m: std::sync::Arc<dyn material::Material>,
     ) -> Self {
         return Self {
             center: cen,
@@ -35,6 +37,10 @@ impl Sphere {
         return &self.radius;
     }
 
+    pub fn material(&self) -> &std::sync::Arc<dyn material::Material> {
+        return &self.mat;
+    }
+
     fn get_sphere_uv(p: &ray::Point, u: &mut RayTracingFloat, v: &mut RayTracingFloat) {
         // p: a given point on the sphere of radius one, centered at the origin.
         // u: returned value [0,1] of angle around the Y axis from X=-1.
@@ -49,6 +55,23 @@ impl Sphere {
         *u = phi / (2.0 * std::f64::consts::PI);
         *v = theta / std::f64::consts::PI;
     }
+
+    // Random direction around the z axis within the cone a sphere at the given squared distance
+    // covers, distributed uniformly over the solid angle.
+    fn random_to_sphere(
+        radius: &RayTracingFloat,
+        distance_squared: &RayTracingFloat,
+        u: &(RayTracingFloat, RayTracingFloat),
+    ) -> ray::Vector {
+        let (r1, r2) = *u;
+        let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let x = phi.cos() * (1.0 - z * z).sqrt();
+        let y = phi.sin() * (1.0 - z * z).sqrt();
+
+        return ray::Vector::new(x, y, z);
+    }
 }
 
 impl hittable::Hittable for Sphere {
@@ -84,7 +107,7 @@ impl hittable::Hittable for Sphere {
         let outward_normal = (&rec.p - self.center()) / self.radius();
         rec.set_face_normal(r, &outward_normal);
         Self::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
-        rec.mat = std::rc::Rc::downgrade(&self.mat);
+        rec.mat = std::sync::Arc::downgrade(&self.mat);
 
         return true;
     }
@@ -101,6 +124,47 @@ impl hittable::Hittable for Sphere {
         );
         return true;
     }
+
+    fn pdf_value(&self, origin: &ray::Point, direction: &ray::Vector) -> RayTracingFloat {
+        let mut rec = hittable::HitRecord::new();
+        if !self.hit(
+            &ray::Ray::new(origin.clone(), direction.clone(), 0.0),
+            &0.001,
+            &RayTracingFloat::INFINITY,
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center() - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // From inside, directions are sampled over the whole sphere.
+            return 1.0 / (4.0 * std::f64::consts::PI);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - cos_theta_max);
+        return 1.0 / solid_angle;
+    }
+
+    fn random(&self, origin: &ray::Point, sampler: &mut dyn sampler::Sampler) -> ray::Vector {
+        let direction = self.center() - origin;
+        let distance_squared = direction.length_squared();
+        let u = sampler.get_2d();
+        if distance_squared <= self.radius * self.radius {
+            return ray::Vector::sample_unit_vector(&u);
+        }
+        let uvw = onb::OrthonormalBasis::build_from_w(&direction);
+        return uvw.local(&Self::random_to_sphere(&self.radius, &distance_squared, &u));
+    }
+
+    fn is_light(&self) -> bool {
+        return self.mat.emits();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
@@ -112,7 +176,7 @@ pub struct MovingSphere {
     time0: RayTracingFloat,
     time1: RayTracingFloat,
     radius: RayTracingFloat,
-    mat: std::rc::Rc<dyn material::Material>,
+    mat: std::sync::Arc<dyn material::Material>,
 }
 
 impl MovingSphere {
@@ -122,7 +186,7 @@ impl MovingSphere {
         _time0: RayTracingFloat,
         _time1: RayTracingFloat,
         r: RayTracingFloat,
-        m: std::rc::Rc<dyn material::Material>,
+        m: std::sync::Arc<dyn material::Material>,
     ) -> Self {
         return Self {
             center0: cen0,
@@ -136,13 +200,32 @@ impl MovingSphere {
 
     pub fn center(&self, time: &RayTracingFloat) -> ray::Point {
         return &self.center0
-            + (&self.center1 - &self.center0)
-                * ((time - &self.time0) / (&self.time1 - &self.time0));
+            + (&self.center1 - &self.center0) * ((time - self.time0) / (self.time1 - self.time0));
+    }
+
+    pub fn center0(&self) -> &ray::Point {
+        return &self.center0;
+    }
+
+    pub fn center1(&self) -> &ray::Point {
+        return &self.center1;
+    }
+
+    pub fn time0(&self) -> &RayTracingFloat {
+        return &self.time0;
+    }
+
+    pub fn time1(&self) -> &RayTracingFloat {
+        return &self.time1;
     }
 
     pub fn radius(&self) -> &RayTracingFloat {
         return &self.radius;
     }
+
+    pub fn material(&self) -> &std::sync::Arc<dyn material::Material> {
+        return &self.mat;
+    }
 }
 
 impl hittable::Hittable for MovingSphere {
@@ -177,7 +260,7 @@ impl hittable::Hittable for MovingSphere {
         rec.p = r.at(&rec.t);
         let outward_normal = (&rec.p - self.center(r.time())) / self.radius();
         rec.set_face_normal(r, &outward_normal);
-        rec.mat = std::rc::Rc::downgrade(&self.mat);
+        rec.mat = std::sync::Arc::downgrade(&self.mat);
 
         return true;
     }
@@ -199,4 +282,8 @@ impl hittable::Hittable for MovingSphere {
         *output_box = aabb::surrounding_box(&box0, &box1);
         return true;
     }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
@@ -0,0 +1,376 @@
+use super::aabb;
+use super::color;
+use super::density;
+use super::hittable;
+use super::material;
+use super::ray;
+use super::sampler;
+use super::texture;
+use super::utils::RayTracingFloat;
+
+//
+// Constant Medium
+//
+// A volume of uniform density filling a closed boundary, like smoke or fog. Rays passing through
+// it are scattered at random distances, the denser the medium the sooner. The boundary's own
+// material is not used.
+pub struct ConstantMedium {
+    boundary: std::sync::Arc<dyn hittable::Hittable>,
+    density: RayTracingFloat,
+    phase_function: std::sync::Arc<material::Isotropic>,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        b: std::sync::Arc<dyn hittable::Hittable>,
+        d: RayTracingFloat,
+        c: color::Color,
+    ) -> Self {
+        return Self {
+            boundary: b,
+            density: d,
+            phase_function: std::sync::Arc::new(material::Isotropic::new(c)),
+        };
+    }
+
+    pub fn new_with_texture(
+        b: std::sync::Arc<dyn hittable::Hittable>,
+        d: RayTracingFloat,
+        a: std::sync::Arc<dyn texture::Texture>,
+    ) -> Self {
+        return Self {
+            boundary: b,
+            density: d,
+            phase_function: std::sync::Arc::new(material::Isotropic::new_with_texture(a)),
+        };
+    }
+
+    pub fn boundary(&self) -> &std::sync::Arc<dyn hittable::Hittable> {
+        return &self.boundary;
+    }
+
+    pub fn density(&self) -> &RayTracingFloat {
+        return &self.density;
+    }
+
+    pub fn albedo(&self) -> &std::sync::Arc<dyn texture::Texture> {
+        return self.phase_function.albedo();
+    }
+
+    // The part of the ray between t_min and t_max that lies inside the boundary, also if the ray
+    // starts inside.
+    fn inside(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> Option<(RayTracingFloat, RayTracingFloat)> {
+        let mut rec1 = hittable::HitRecord::new();
+        let mut rec2 = hittable::HitRecord::new();
+        if !self.boundary.hit(
+            r,
+            &RayTracingFloat::NEG_INFINITY,
+            &RayTracingFloat::INFINITY,
+            &mut rec1,
+        ) {
+            return None;
+        }
+        if !self
+            .boundary
+            .hit(r, &(rec1.t + 0.0001), &RayTracingFloat::INFINITY, &mut rec2)
+        {
+            return None;
+        }
+
+        let t0 = rec1.t.max(*t_min).max(0.0);
+        let t1 = rec2.t.min(*t_max);
+        if t0 >= t1 {
+            return None;
+        }
+        return Some((t0, t1));
+    }
+}
+
+impl hittable::Hittable for ConstantMedium {
+    fn hit(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut hittable::HitRecord,
+    ) -> bool {
+        let (t0, t1) = match self.inside(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return false,
+        };
+
+        // The distance comes from the random key the renderer drew for the ray from the sampler,
+        // mixed with where the ray enters so that each medium along the ray gets its own.
+        let u = sampler::hash_to_unit(&[r.random_key().clone(), t0.to_bits()]);
+
+        let ray_length = r.direction().length();
+        let distance_inside_boundary = (t1 - t0) * ray_length;
+        let hit_distance = -(1.0 - u).ln() / self.density;
+        if hit_distance > distance_inside_boundary {
+            return false;
+        }
+
+        rec.t = t0 + hit_distance / ray_length;
+        rec.p = r.at(&rec.t);
+        // Scattering inside a volume has no surface, so any normal will do.
+        rec.set_face_normal(r, &-r.direction().unit_vector());
+        rec.u = 0.0;
+        rec.v = 0.0;
+        rec.mat = std::sync::Arc::<material::Isotropic>::downgrade(&self.phase_function);
+
+        return true;
+    }
+
+    fn hit_surface(
+        &self,
+        _r: &ray::Ray,
+        _t_min: &RayTracingFloat,
+        _t_max: &RayTracingFloat,
+        _rec: &mut hittable::HitRecord,
+    ) -> bool {
+        return false;
+    }
+
+    fn transmittance(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        return match self.inside(r, t_min, t_max) {
+            Some((t0, t1)) => (-self.density * (t1 - t0) * r.direction().length()).exp(),
+            None => 1.0,
+        };
+    }
+
+    fn bounding_box(
+        &self,
+        time0: &RayTracingFloat,
+        time1: &RayTracingFloat,
+        output_box: &mut aabb::AxisAlignedBoundingBoxes,
+    ) -> bool {
+        return self.boundary.bounding_box(time0, time1, output_box);
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Heterogeneous Medium
+//
+// A volume of varying density, like a cloud, filling an axis aligned box. The density field
+// gives the density at each point relative to the density of the medium. Distances are sampled
+// by delta tracking and transmittance is estimated by ratio tracking (see Novák et al., "Monte
+// Carlo Methods for Volumetric Light Transport Simulation", 2018): both step through the volume
+// as if it had the maximum density everywhere, which keeps them unbiased however the density
+// varies.
+pub struct HeterogeneousMedium {
+    box_min: ray::Point,
+    box_max: ray::Point,
+    density: RayTracingFloat,
+    field: std::sync::Arc<dyn density::DensityField>,
+    phase_function: std::sync::Arc<material::HenyeyGreenstein>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        p0: ray::Point,
+        p1: ray::Point,
+        d: RayTracingFloat,
+        field: std::sync::Arc<dyn density::DensityField>,
+        a: std::sync::Arc<dyn texture::Texture>,
+        g: RayTracingFloat,
+    ) -> Self {
+        return Self {
+            box_min: p0,
+            box_max: p1,
+            density: d,
+            field: field,
+            phase_function: std::sync::Arc::new(material::HenyeyGreenstein::new_with_texture(a, g)),
+        };
+    }
+
+    pub fn min(&self) -> &ray::Point {
+        return &self.box_min;
+    }
+
+    pub fn max(&self) -> &ray::Point {
+        return &self.box_max;
+    }
+
+    pub fn density(&self) -> &RayTracingFloat {
+        return &self.density;
+    }
+
+    pub fn field(&self) -> &std::sync::Arc<dyn density::DensityField> {
+        return &self.field;
+    }
+
+    pub fn albedo(&self) -> &std::sync::Arc<dyn texture::Texture> {
+        return self.phase_function.albedo();
+    }
+
+    pub fn anisotropy(&self) -> &RayTracingFloat {
+        return self.phase_function.anisotropy();
+    }
+
+    // The part of the ray between t_min and t_max that lies inside the box.
+    fn inside(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> Option<(RayTracingFloat, RayTracingFloat)> {
+        let mut t0 = t_min.max(0.0);
+        let mut t1 = *t_max;
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let mut near = (self.box_min[a] - r.origin()[a]) * inv_d;
+            let mut far = (self.box_max[a] - r.origin()[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // A ray along a face gives NaN, which max and min leave out.
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+        }
+        if t0 >= t1 {
+            return None;
+        }
+        return Some((t0, t1));
+    }
+
+    fn density_at(&self, p: &ray::Point) -> RayTracingFloat {
+        let size = &self.box_max - &self.box_min;
+        let local = p - &self.box_min;
+        let unit = ray::Point::new(
+            local.x() / size.x(),
+            local.y() / size.y(),
+            local.z() / size.z(),
+        );
+        return self.density * self.field.density(&unit);
+    }
+
+    // Key for the random numbers of a ray in this medium, from the random key the renderer drew
+    // for the ray, see ConstantMedium::hit.
+    fn random_key(r: &ray::Ray, t0: &RayTracingFloat) -> u64 {
+        return sampler::hash(&[r.random_key().clone(), t0.to_bits()]);
+    }
+}
+
+impl hittable::Hittable for HeterogeneousMedium {
+    fn hit(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+        rec: &mut hittable::HitRecord,
+    ) -> bool {
+        let (t0, t1) = match self.inside(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let majorant = self.density * self.field.max_density();
+        if majorant <= 0.0 {
+            return false;
+        }
+
+        // Delta tracking: a tentative collision is a real one with the probability of the density
+        // there relative to the majorant, otherwise the ray carries on.
+        let key = Self::random_key(r, &t0);
+        let step = 1.0 / (majorant * r.direction().length());
+        let mut t = t0;
+        let mut i = 0;
+        loop {
+            t -= (1.0 - sampler::hash_to_unit(&[key, i])).ln() * step;
+            if t >= t1 {
+                return false;
+            }
+            let p = r.at(&t);
+            if sampler::hash_to_unit(&[key, i + 1]) * majorant < self.density_at(&p) {
+                rec.t = t;
+                rec.p = p;
+                rec.set_face_normal(r, &-r.direction().unit_vector());
+                rec.u = 0.0;
+                rec.v = 0.0;
+                rec.mat =
+                    std::sync::Arc::<material::HenyeyGreenstein>::downgrade(&self.phase_function);
+                return true;
+            }
+            i += 2;
+        }
+    }
+
+    fn hit_surface(
+        &self,
+        _r: &ray::Ray,
+        _t_min: &RayTracingFloat,
+        _t_max: &RayTracingFloat,
+        _rec: &mut hittable::HitRecord,
+    ) -> bool {
+        return false;
+    }
+
+    fn transmittance(
+        &self,
+        r: &ray::Ray,
+        t_min: &RayTracingFloat,
+        t_max: &RayTracingFloat,
+    ) -> RayTracingFloat {
+        let (t0, t1) = match self.inside(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return 1.0,
+        };
+        let majorant = self.density * self.field.max_density();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+
+        // Ratio tracking: every tentative collision lets through the share of light a real one
+        // would not have stopped.
+        let key = Self::random_key(r, &t0);
+        let step = 1.0 / (majorant * r.direction().length());
+        let mut transmittance = 1.0;
+        let mut t = t0;
+        let mut i = 0;
+        loop {
+            t -= (1.0 - sampler::hash_to_unit(&[key, i])).ln() * step;
+            if t >= t1 {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density_at(&r.at(&t)) / majorant;
+
+            // Russian roulette ends the walk through dense volumes once little light is left.
+            if transmittance < 0.1 {
+                let q = (1.0 - transmittance).max(0.05);
+                if sampler::hash_to_unit(&[key, i + 1]) < q {
+                    return 0.0;
+                }
+                transmittance /= 1.0 - q;
+            }
+            i += 2;
+        }
+    }
+
+    fn bounding_box(
+        &self,
+        _time0: &RayTracingFloat,
+        _time1: &RayTracingFloat,
+        output_box: &mut aabb::AxisAlignedBoundingBoxes,
+    ) -> bool {
+        *output_box =
+            aabb::AxisAlignedBoundingBoxes::new(self.box_min.clone(), self.box_max.clone());
+        return true;
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
@@ -0,0 +1,175 @@
+// Reads images into linear colors in the working space, e.g. for environment maps. Radiance HDR
+// files are linear already, PNG files are taken to be sRGB encoded.
+use super::color;
+use super::utils::RayTracingFloat;
+
+// An image as linear colors, stored row by row from the top.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<color::Color>,
+}
+
+pub fn read_image(path: &str) -> Result<Image, String> {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let image = match extension.as_deref() {
+        Some("hdr") => std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| decode_hdr(&data)),
+        Some("png") => decode_png(path),
+        _ => Err(String::from(
+            "cannot read this kind of image, use a .hdr or .png file",
+        )),
+    };
+    return image.map_err(|err| format!("{}: {}", path, err));
+}
+
+//
+// PNG
+//
+fn decode_png(path: &str) -> Result<Image, String> {
+    let bitmap = lodepng::decode24_file(path).map_err(|err| format!("{:?}", err))?;
+    let srgb = color::TransferFunction::Srgb;
+    let channel = |c: u8| srgb.decode(&(c as RayTracingFloat / 255.0));
+    return Ok(Image {
+        width: bitmap.width,
+        height: bitmap.height,
+        pixels: bitmap
+            .buffer
+            .iter()
+            .map(|p| color::Color::new(channel(p.r), channel(p.g), channel(p.b)))
+            .collect(),
+    });
+}
+
+//
+// Radiance HDR
+//
+
+// Expands the shared exponent format written by image_output back into a color, taking every
+// mantissa from the middle of the range it stands for.
+fn from_rgbe(rgbe: &[u8]) -> color::Color {
+    if rgbe[3] == 0 {
+        return color::Color::zero();
+    }
+    let scale = (2.0 as RayTracingFloat).powi(rgbe[3] as i32 - 136);
+    return color::Color::new(
+        (rgbe[0] as RayTracingFloat + 0.5) * scale,
+        (rgbe[1] as RayTracingFloat + 0.5) * scale,
+        (rgbe[2] as RayTracingFloat + 0.5) * scale,
+    );
+}
+
+// Reads one channel of a run-length encoded scanline, see encode_hdr_channel in image_output.
+fn decode_hdr_channel(data: &[u8], pos: &mut usize, out: &mut [u8]) -> Result<(), String> {
+    let truncated = || String::from("image data is truncated");
+    let mut x = 0;
+    while x < out.len() {
+        let count = *data.get(*pos).ok_or_else(truncated)? as usize;
+        *pos += 1;
+        if count > 128 {
+            let run = count - 128;
+            let value = *data.get(*pos).ok_or_else(truncated)?;
+            *pos += 1;
+            if x + run > out.len() {
+                return Err(String::from("run overflows the scanline"));
+            }
+            out[x..x + run].fill(value);
+            x += run;
+        } else {
+            if count == 0 || x + count > out.len() {
+                return Err(String::from("run overflows the scanline"));
+            }
+            let bytes = data.get(*pos..*pos + count).ok_or_else(truncated)?;
+            out[x..x + count].copy_from_slice(bytes);
+            *pos += count;
+            x += count;
+        }
+    }
+    return Ok(());
+}
+
+fn decode_hdr(data: &[u8]) -> Result<Image, String> {
+    // The header is a list of lines ending with an empty one, followed by the resolution.
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    loop {
+        let end = data[pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| String::from("header is truncated"))?;
+        let line = String::from_utf8_lossy(&data[pos..pos + end]).to_string();
+        pos += end + 1;
+        if lines.is_empty() && !line.starts_with("#?") {
+            return Err(String::from("not a Radiance HDR file"));
+        }
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    if let Some(format) = lines.iter().find_map(|l| l.strip_prefix("FORMAT=")) {
+        if format != "32-bit_rle_rgbe" {
+            return Err(format!("unsupported pixel format '{}'", format));
+        }
+    }
+
+    let end = data[pos..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or_else(|| String::from("resolution is missing"))?;
+    let resolution = String::from_utf8_lossy(&data[pos..pos + end]).to_string();
+    pos += end + 1;
+    let fields = resolution.split_whitespace().collect::<Vec<_>>();
+    let (height, width) = match fields.as_slice() {
+        ["-Y", height, "+X", width] => (height.parse::<usize>(), width.parse::<usize>()),
+        _ => {
+            return Err(format!(
+                "unsupported image orientation '{}', only -Y <height> +X <width> is read",
+                resolution
+            ))
+        }
+    };
+    let (height, width) = match (height, width) {
+        (Ok(height), Ok(width)) if height > 0 && width > 0 => (height, width),
+        _ => return Err(format!("invalid resolution '{}'", resolution)),
+    };
+    // Even run-length encoded, a scanline takes at least a byte per 16 pixels.
+    if width.saturating_mul(height) / 16 > data.len() {
+        return Err(String::from("image data is truncated"));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; width * 4];
+    let mut channel = vec![0u8; width];
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width)
+            && data.get(pos..pos + 4)
+                == Some(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8][..]);
+        if rle {
+            pos += 4;
+            for c in 0..4 {
+                decode_hdr_channel(data, &mut pos, &mut channel)?;
+                for x in 0..width {
+                    scanline[x * 4 + c] = channel[x];
+                }
+            }
+        } else {
+            let bytes = data
+                .get(pos..pos + width * 4)
+                .ok_or_else(|| String::from("image data is truncated"))?;
+            scanline.copy_from_slice(bytes);
+            pos += width * 4;
+        }
+        pixels.extend(scanline.chunks(4).map(from_rgbe));
+    }
+
+    return Ok(Image {
+        width: width,
+        height: height,
+        pixels: pixels,
+    });
+}
@@ -0,0 +1,272 @@
+use super::aov;
+use super::color;
+use super::exr;
+use super::tonemap;
+use super::utils::RayTracingFloat;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png, // 8-bit, display encoded
+    Hdr, // Radiance RGBE, linear
+    Pfm, // Portable Float Map, linear 32-bit float
+    Exr, // OpenEXR, linear 32-bit float RGBA
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Result<Self, String> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        return match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("exr") => Ok(ImageFormat::Exr),
+            _ => Err(format!(
+                "cannot tell the image format of '{}', use a .png, .hdr, .pfm or .exr extension",
+                path
+            )),
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Hdr => "hdr",
+            ImageFormat::Pfm => "pfm",
+            ImageFormat::Exr => "exr",
+        };
+    }
+}
+
+// Writes an image given as linear radiance, stored row by row from the top of the image, along
+// with any auxiliary passes. EXR files hold the passes as additional channels, other formats get
+// a file per pass next to the image. Tone mapping and the output color space only apply to
+// display formats, floating point formats keep the linear values of the working space.
+pub fn write_image(
+    path: &str,
+    pixels: &[color::Color],
+    image_width: &usize,
+    image_height: &usize,
+    passes: &[aov::Pass],
+    tone_mapping: &tonemap::ToneMapping,
+    color_space: &color::ColorSpace,
+) -> Result<ImageFormat, String> {
+    let format = ImageFormat::from_path(path)?;
+    let bytes = match format {
+        ImageFormat::Exr => encode_exr(pixels, image_width, image_height, passes),
+        _ => encode(
+            &format,
+            pixels,
+            image_width,
+            image_height,
+            tone_mapping,
+            color_space,
+        ),
+    }
+    .map_err(|err| format!("{}: {}", path, err))?;
+    std::fs::write(path, bytes).map_err(|err| format!("{}: {}", path, err))?;
+
+    if format != ImageFormat::Exr {
+        // Passes hold data rather than pictures, so they are shown as is without tone mapping
+        // and, apart from the albedo, without display encoding.
+        let clamp = tonemap::ToneMapping::new(0.0, std::sync::Arc::new(tonemap::Clamp {}));
+        for pass in passes {
+            let pass_path = aov::pass_path(path, &pass.aov);
+            let (pass_pixels, pass_color_space) = match format {
+                ImageFormat::Png if pass.aov != aov::Aov::Albedo => (
+                    pass.visualize(),
+                    color::ColorSpace::new(
+                        color::Primaries::Rec709,
+                        color::TransferFunction::Linear,
+                    ),
+                ),
+                ImageFormat::Png => (pass.visualize(), *color_space),
+                _ => (pass.pixels.clone(), *color_space),
+            };
+            let bytes = encode(
+                &format,
+                &pass_pixels,
+                image_width,
+                image_height,
+                &clamp,
+                &pass_color_space,
+            )
+            .map_err(|err| format!("{}: {}", pass_path, err))?;
+            std::fs::write(&pass_path, bytes).map_err(|err| format!("{}: {}", pass_path, err))?;
+        }
+    }
+    return Ok(format);
+}
+
+// Encodes a single RGB image in a format other than EXR.
+fn encode(
+    format: &ImageFormat,
+    pixels: &[color::Color],
+    image_width: &usize,
+    image_height: &usize,
+    tone_mapping: &tonemap::ToneMapping,
+    color_space: &color::ColorSpace,
+) -> Result<Vec<u8>, String> {
+    return match format {
+        ImageFormat::Png => {
+            let buffer = pixels
+                .iter()
+                .map(|pixel| color::write_color(pixel, tone_mapping, color_space))
+                .collect::<Vec<_>>();
+            lodepng::encode32(&buffer, *image_width, *image_height)
+                .map_err(|err| format!("{:?}", err))
+        }
+        ImageFormat::Hdr => Ok(encode_hdr(pixels, image_width, image_height)),
+        ImageFormat::Pfm => Ok(encode_pfm(pixels, image_width, image_height)),
+        ImageFormat::Exr => encode_exr(pixels, image_width, image_height, &[]),
+    };
+}
+
+//
+// Radiance HDR
+//
+
+// Converts to the shared exponent format: a byte of mantissa per channel plus a common exponent,
+// chosen so that the largest channel has its mantissa in [128, 256).
+fn to_rgbe(pixel: &color::Color) -> [u8; 4] {
+    let r = pixel.x().max(0.0);
+    let g = pixel.y().max(0.0);
+    let b = pixel.z().max(0.0);
+    let v = r.max(g).max(b);
+    if !v.is_finite() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / (2.0 as RayTracingFloat).powi(e) >= 1.0 {
+        e += 1;
+    }
+    let scale = 256.0 / (2.0 as RayTracingFloat).powi(e);
+    return [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128).clamp(0, 255) as u8,
+    ];
+}
+
+// Run-length encodes one channel of a scanline the way Radiance does: runs of identical bytes
+// become (128 + count, value), everything else is copied as (count, bytes...).
+fn encode_hdr_channel(data: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4;
+    let mut pos = 0;
+    while pos < data.len() {
+        // Find the next run long enough to be worth encoding.
+        let mut run_start = pos;
+        let mut run_length = 0;
+        while run_start < data.len() {
+            run_length = 1;
+            while run_start + run_length < data.len()
+                && run_length < 127
+                && data[run_start + run_length] == data[run_start]
+            {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        // Copy whatever comes before the run literally.
+        while pos < run_start {
+            let count = (run_start - pos).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&data[pos..pos + count]);
+            pos += count;
+        }
+
+        if run_length >= MIN_RUN && run_start < data.len() {
+            out.push((128 + run_length) as u8);
+            out.push(data[run_start]);
+            pos += run_length;
+        }
+    }
+}
+
+fn encode_hdr(pixels: &[color::Color], image_width: &usize, image_height: &usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(b"#?RADIANCE\n");
+    out.extend_from_slice(b"FORMAT=32-bit_rle_rgbe\n\n");
+    out.extend_from_slice(format!("-Y {} +X {}\n", image_height, image_width).as_bytes());
+
+    // Run-length encoding is only defined for scanlines between 8 and 32767 pixels wide.
+    let rle = (8..0x8000).contains(image_width);
+    for row in pixels.chunks(*image_width) {
+        let rgbe = row.iter().map(to_rgbe).collect::<Vec<_>>();
+        if !rle {
+            for pixel in &rgbe {
+                out.extend_from_slice(pixel);
+            }
+            continue;
+        }
+
+        out.extend_from_slice(&[2, 2, (image_width >> 8) as u8, (image_width & 0xff) as u8]);
+        for channel in 0..4 {
+            let data = rgbe.iter().map(|p| p[channel]).collect::<Vec<_>>();
+            encode_hdr_channel(&data, &mut out);
+        }
+    }
+    return out;
+}
+
+//
+// Portable Float Map
+//
+fn encode_pfm(pixels: &[color::Color], image_width: &usize, image_height: &usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * 12 + 32);
+    // A negative scale marks the data as little-endian.
+    out.extend_from_slice(format!("PF\n{} {}\n-1.0\n", image_width, image_height).as_bytes());
+
+    // Scanlines are stored from the bottom of the image to the top.
+    for row in pixels.chunks(*image_width).rev() {
+        for pixel in row {
+            for c in 0..3 {
+                out.extend_from_slice(&(pixel[c] as f32).to_le_bytes());
+            }
+        }
+    }
+    return out;
+}
+
+//
+// OpenEXR
+//
+fn encode_exr(
+    pixels: &[color::Color],
+    image_width: &usize,
+    image_height: &usize,
+    passes: &[aov::Pass],
+) -> Result<Vec<u8>, String> {
+    let channel = |data: &[color::Color], c: usize| data.iter().map(|p| p[c] as f32).collect();
+
+    // Names and values of all channels, the passes are stored as "<pass>.<channel>" layers.
+    let mut channels: Vec<(String, Vec<f32>)> = vec![
+        (String::from("R"), channel(pixels, 0)),
+        (String::from("G"), channel(pixels, 1)),
+        (String::from("B"), channel(pixels, 2)),
+        (String::from("A"), vec![1.0f32; pixels.len()]),
+    ];
+    for pass in passes {
+        for (c, name) in pass.aov.channels().iter().enumerate() {
+            channels.push((
+                format!("{}.{}", pass.aov.name(), name),
+                channel(&pass.pixels, c),
+            ));
+        }
+    }
+
+    let channels = channels
+        .iter()
+        .map(|(name, data)| exr::Channel::new(name, data))
+        .collect::<Vec<_>>();
+    return exr::encode(image_width, image_height, &channels);
+}
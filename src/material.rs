@@ -1,53 +1,114 @@
 use super::color;
 use super::hittable;
+use super::onb;
 use super::ray;
+use super::sampler;
 use super::texture;
 use super::utils;
 use super::utils::RayTracingFloat;
 use super::vec3;
 
-pub trait Material {
+// The outcome of scattering a ray off a surface.
+pub struct ScatterRecord {
+    pub scattered: ray::Ray,
+    pub attenuation: color::Color,
+    // Probability density of having sampled the scattered direction, per solid angle. Specular
+    // scattering picks a single direction and has no meaningful density.
+    pub pdf: RayTracingFloat,
+    pub is_specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn new() -> Self {
+        return Self {
+            scattered: ray::Ray::zero(),
+            attenuation: color::Color::zero(),
+            pdf: 0.0,
+            is_specular: false,
+        };
+    }
+}
+
+pub trait Material: Send + Sync {
     fn emitted(
         &self,
         _u: &RayTracingFloat,
         _v: &RayTracingFloat,
         _p: &ray::Point,
     ) -> &color::Color {
-        use std::mem::MaybeUninit;
-        static mut DEFAULT_COLOR: MaybeUninit<color::Color> = MaybeUninit::uninit();
-        static INIT: std::sync::Once = std::sync::Once::new();
-        unsafe {
-            INIT.call_once(|| DEFAULT_COLOR = MaybeUninit::new(color::Color::zero()));
-            return &*DEFAULT_COLOR.as_ptr();
-        }
+        static DEFAULT_COLOR: std::sync::OnceLock<color::Color> = std::sync::OnceLock::new();
+        return DEFAULT_COLOR.get_or_init(color::Color::zero);
     }
 
+    // Samples a scattered ray, returns false if the ray is absorbed.
     fn scatter(
         &self,
         r_in: &ray::Ray,
         rec: &hittable::HitRecord,
-        attenuation: &mut color::Color,
-        scattered: &mut ray::Ray,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn sampler::Sampler,
     ) -> bool;
+
+    // Probability density, per solid angle, of light arriving along the scattered ray leaving in
+    // the direction opposite to r_in. Together with the attenuation this is the BSDF times the
+    // cosine term, for all but specular scattering.
+    fn scattering_pdf(
+        &self,
+        _r_in: &ray::Ray,
+        _rec: &hittable::HitRecord,
+        _scattered: &ray::Ray,
+    ) -> RayTracingFloat {
+        return 0.0;
+    }
+
+    // Probability density, per solid angle, with which scatter picks the direction of the
+    // scattered ray. Needed to weigh scattered rays against light samples. Materials which sample
+    // their scattering_pdf exactly need not override this.
+    fn sampling_pdf(
+        &self,
+        r_in: &ray::Ray,
+        rec: &hittable::HitRecord,
+        scattered: &ray::Ray,
+    ) -> RayTracingFloat {
+        return self.scattering_pdf(r_in, rec, scattered);
+    }
+
+    // Whether the material gives off light, which makes objects using it light sources.
+    fn emits(&self) -> bool {
+        return false;
+    }
+
+    // The color of the surface, as written to the albedo pass. Materials without a color of their
+    // own, like glass, are white.
+    fn albedo(&self, _rec: &hittable::HitRecord) -> color::Color {
+        return color::Color::new(1.0, 1.0, 1.0);
+    }
+
+    // Gives access to the concrete type, e.g. for writing the scene graph out to a file.
+    fn as_any(&self) -> &dyn std::any::Any;
 }
 
 //
 // Lambertian
 //
 pub struct Lambertian {
-    albedo: std::rc::Rc<dyn texture::Texture>,
+    albedo: std::sync::Arc<dyn texture::Texture>,
 }
 
 impl Lambertian {
     pub fn new(color: color::Color) -> Self {
         return Self {
-            albedo: std::rc::Rc::new(texture::SolidColor::new(color)),
+            albedo: std::sync::Arc::new(texture::SolidColor::new(color)),
         };
     }
 
-    pub fn new_with_texture(a: std::rc::Rc<dyn texture::Texture>) -> Self {
+    pub fn new_with_texture(a: std::sync::Arc<dyn texture::Texture>) -> Self {
         return Self { albedo: a };
     }
+
+    pub fn albedo(&self) -> &std::sync::Arc<dyn texture::Texture> {
+        return &self.albedo;
+    }
 }
 
 impl Material for Lambertian {
@@ -55,22 +116,45 @@ impl Material for Lambertian {
         &self,
         r_in: &ray::Ray,
         rec: &hittable::HitRecord,
-        attenuation: &mut color::Color,
-        scattered: &mut ray::Ray,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn sampler::Sampler,
     ) -> bool {
-        //// let mut scatter_direction = rec.point() + rec.normal() + ray::Vector::random_unit_vector();
-        let mut scatter_direction = rec.normal() + ray::Vector::random_unit_vector();
-        // let mut scatter_direction = rec.normal() + ray::Vector::random_in_unit_sphere();
-        // let mut scatter_direction = ray::Vector::random_in_hemisphere(rec.normal());
-
-        // Catch degenerate scatter direction
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal().clone();
-        }
-        *scattered = ray::Ray::new(rec.point().clone(), scatter_direction, r_in.time().clone());
-        *attenuation = self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+        // Cosine weighted sampling of the hemisphere around the normal.
+        let uvw = onb::OrthonormalBasis::build_from_w(rec.normal());
+        let scatter_direction = uvw.local(&ray::Vector::sample_cosine_direction(&sampler.get_2d()));
+
+        srec.scattered = ray::Ray::new(
+            rec.point().clone(),
+            scatter_direction.unit_vector(),
+            r_in.time().clone(),
+        );
+        srec.attenuation = self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+        srec.pdf = vec3::dot(uvw.w(), srec.scattered.direction()) / std::f64::consts::PI;
+        srec.is_specular = false;
         return true;
     }
+
+    fn scattering_pdf(
+        &self,
+        _r_in: &ray::Ray,
+        rec: &hittable::HitRecord,
+        scattered: &ray::Ray,
+    ) -> RayTracingFloat {
+        let cosine = vec3::dot(rec.normal(), &scattered.direction().unit_vector());
+        return if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / std::f64::consts::PI
+        };
+    }
+
+    fn albedo(&self, rec: &hittable::HitRecord) -> color::Color {
+        return self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
@@ -88,6 +172,33 @@ impl Metal {
             fuzz: utils::clamp(f, 0.0, 1.0),
         };
     }
+
+    pub fn albedo(&self) -> &color::Color {
+        return &self.albedo;
+    }
+
+    pub fn fuzz(&self) -> &RayTracingFloat {
+        return &self.fuzz;
+    }
+
+    // Density of the directions picked by fuzzy reflection: the mirror direction, offset by a
+    // point picked uniformly within a ball of radius fuzz. This is the fraction of the ball's
+    // volume within an infinitesimal cone around the direction.
+    fn fuzz_pdf(&self, reflected: &ray::Vector, direction: &ray::Vector) -> RayTracingFloat {
+        // Distances along the direction where it enters and leaves the ball.
+        let b = vec3::dot(reflected, direction);
+        let discriminant = b * b - (1.0 - self.fuzz * self.fuzz);
+        if discriminant < 0.0 {
+            return 0.0;
+        }
+        let t_far = b + discriminant.sqrt();
+        let t_near = (b - discriminant.sqrt()).max(0.0);
+        if t_far <= 0.0 {
+            return 0.0;
+        }
+        let volume = 4.0 / 3.0 * std::f64::consts::PI * self.fuzz.powi(3);
+        return (t_far.powi(3) - t_near.powi(3)) / (3.0 * volume);
+    }
 }
 
 impl Material for Metal {
@@ -95,16 +206,56 @@ impl Material for Metal {
         &self,
         r_in: &ray::Ray,
         rec: &hittable::HitRecord,
-        attenuation: &mut color::Color,
-        scattered: &mut ray::Ray,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn sampler::Sampler,
     ) -> bool {
-        let mut reflected = ray::reflect(&r_in.direction().unit_vector(), rec.normal());
-        if self.fuzz > 0.0 {
-            reflected += ray::Vector::random_in_unit_sphere() * self.fuzz;
+        let reflected = ray::reflect(&r_in.direction().unit_vector(), rec.normal());
+        srec.attenuation = self.albedo.clone();
+        if self.fuzz <= 0.0 {
+            srec.scattered = ray::Ray::new(rec.point().clone(), reflected, r_in.time().clone());
+            srec.pdf = 0.0;
+            srec.is_specular = true;
+            return vec3::dot(srec.scattered.direction(), rec.normal()) > 0.0;
+        }
+
+        // Fuzzy reflections perturb the mirror direction. They have a density, which lets them be
+        // combined with light samples. Directions below the surface are absorbed through
+        // scattering_pdf, not by failing to scatter, so that light is still sampled here.
+        let offset = ray::Vector::sample_in_unit_sphere(&sampler.get_2d(), &sampler.get_1d());
+        let mut direction = &reflected + offset * self.fuzz;
+        if direction.near_zero() {
+            direction = reflected.clone();
         }
-        *scattered = ray::Ray::new(rec.point().clone(), reflected, r_in.time().clone());
-        *attenuation = self.albedo.clone();
-        return vec3::dot(scattered.direction(), rec.normal()) > 0.0;
+        srec.scattered = ray::Ray::new(
+            rec.point().clone(),
+            direction.unit_vector(),
+            r_in.time().clone(),
+        );
+        srec.pdf = self.fuzz_pdf(&reflected, srec.scattered.direction());
+        srec.is_specular = false;
+        return true;
+    }
+
+    fn scattering_pdf(
+        &self,
+        r_in: &ray::Ray,
+        rec: &hittable::HitRecord,
+        scattered: &ray::Ray,
+    ) -> RayTracingFloat {
+        let direction = scattered.direction().unit_vector();
+        if self.fuzz <= 0.0 || vec3::dot(&direction, rec.normal()) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = ray::reflect(&r_in.direction().unit_vector(), rec.normal());
+        return self.fuzz_pdf(&reflected, &direction);
+    }
+
+    fn albedo(&self, _rec: &hittable::HitRecord) -> color::Color {
+        return self.albedo.clone();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
     }
 }
 
@@ -122,6 +273,10 @@ impl Dielectric {
         };
     }
 
+    pub fn index_of_refraction(&self) -> &RayTracingFloat {
+        return &self.ir;
+    }
+
     fn reflectance(cosine: RayTracingFloat, ref_idx: RayTracingFloat) -> RayTracingFloat {
         // Use Schlick's approximation for reflectance.
         let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
@@ -135,10 +290,12 @@ impl Material for Dielectric {
         &self,
         r_in: &ray::Ray,
         rec: &hittable::HitRecord,
-        attenuation: &mut color::Color,
-        scattered: &mut ray::Ray,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn sampler::Sampler,
     ) -> bool {
-        *attenuation = color::Color::new(1.0, 1.0, 1.0);
+        srec.attenuation = color::Color::new(1.0, 1.0, 1.0);
+        srec.pdf = 0.0;
+        srec.is_specular = true;
         let refraction_ratio = if *rec.front_face() {
             1.0 / self.ir
         } else {
@@ -150,35 +307,43 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0
-            || Self::reflectance(cos_theta, refraction_ratio) > utils::random_double(&0.0, &1.0);
+            || Self::reflectance(cos_theta, refraction_ratio) > sampler.get_1d();
         let direction = if cannot_refract {
             ray::reflect(&unit_direction, rec.normal())
         } else {
             ray::refract(&unit_direction, rec.normal(), &refraction_ratio)
         };
-        *scattered = ray::Ray::new(rec.point().clone(), direction, r_in.time().clone());
+        srec.scattered = ray::Ray::new(rec.point().clone(), direction, r_in.time().clone());
 
         return true;
     }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // Diffuse Light
 //
 pub struct DiffuseLight {
-    emit: std::rc::Rc<dyn texture::Texture>,
+    emit: std::sync::Arc<dyn texture::Texture>,
 }
 
 impl DiffuseLight {
     pub fn new(color: color::Color) -> Self {
         return Self {
-            emit: std::rc::Rc::new(texture::SolidColor::new(color)),
+            emit: std::sync::Arc::new(texture::SolidColor::new(color)),
         };
     }
 
-    pub fn new_with_texture(a: std::rc::Rc<dyn texture::Texture>) -> Self {
+    pub fn new_with_texture(a: std::sync::Arc<dyn texture::Texture>) -> Self {
         return Self { emit: a };
     }
+
+    pub fn emit(&self) -> &std::sync::Arc<dyn texture::Texture> {
+        return &self.emit;
+    }
 }
 
 impl Material for DiffuseLight {
@@ -186,13 +351,173 @@ impl Material for DiffuseLight {
         return self.emit.value(u, v, p);
     }
 
+    fn emits(&self) -> bool {
+        return true;
+    }
+
     fn scatter(
         &self,
         _r_in: &ray::Ray,
         _rec: &hittable::HitRecord,
-        _attenuation: &mut color::Color,
-        _scattered: &mut ray::Ray,
+        _srec: &mut ScatterRecord,
+        _sampler: &mut dyn sampler::Sampler,
     ) -> bool {
         return false;
     }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Isotropic
+//
+// The phase function of a volume that scatters light equally in all directions.
+pub struct Isotropic {
+    albedo: std::sync::Arc<dyn texture::Texture>,
+}
+
+impl Isotropic {
+    pub fn new(color: color::Color) -> Self {
+        return Self {
+            albedo: std::sync::Arc::new(texture::SolidColor::new(color)),
+        };
+    }
+
+    pub fn new_with_texture(a: std::sync::Arc<dyn texture::Texture>) -> Self {
+        return Self { albedo: a };
+    }
+
+    pub fn albedo(&self) -> &std::sync::Arc<dyn texture::Texture> {
+        return &self.albedo;
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        r_in: &ray::Ray,
+        rec: &hittable::HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> bool {
+        srec.scattered = ray::Ray::new(
+            rec.point().clone(),
+            ray::Vector::sample_unit_vector(&sampler.get_2d()),
+            r_in.time().clone(),
+        );
+        srec.attenuation = self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+        srec.pdf = 1.0 / (4.0 * std::f64::consts::PI);
+        srec.is_specular = false;
+        return true;
+    }
+
+    // Unlike a surface, there is no cosine term: every direction is as likely.
+    fn scattering_pdf(
+        &self,
+        _r_in: &ray::Ray,
+        _rec: &hittable::HitRecord,
+        _scattered: &ray::Ray,
+    ) -> RayTracingFloat {
+        return 1.0 / (4.0 * std::f64::consts::PI);
+    }
+
+    fn albedo(&self, rec: &hittable::HitRecord) -> color::Color {
+        return self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
+}
+
+//
+// Henyey-Greenstein
+//
+// The phase function of a volume that scatters light mostly forward, like clouds, or backward.
+// The anisotropy g in (-1, 1) is the average cosine between the incoming and the scattered
+// direction; with 0 it is the same as Isotropic.
+pub struct HenyeyGreenstein {
+    albedo: std::sync::Arc<dyn texture::Texture>,
+    g: RayTracingFloat,
+}
+
+impl HenyeyGreenstein {
+    pub fn new_with_texture(a: std::sync::Arc<dyn texture::Texture>, g: RayTracingFloat) -> Self {
+        return Self {
+            albedo: a,
+            g: utils::clamp(g, -0.99, 0.99),
+        };
+    }
+
+    pub fn albedo(&self) -> &std::sync::Arc<dyn texture::Texture> {
+        return &self.albedo;
+    }
+
+    pub fn anisotropy(&self) -> &RayTracingFloat {
+        return &self.g;
+    }
+
+    // Density over the sphere, with cos_theta between the direction of travel before and after.
+    fn phase(&self, cos_theta: &RayTracingFloat) -> RayTracingFloat {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        return (1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator * denominator.sqrt());
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(
+        &self,
+        r_in: &ray::Ray,
+        rec: &hittable::HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> bool {
+        // Inverts the distribution of cos_theta (see "Physically Based Rendering", 11.3.1).
+        let (u1, u2) = sampler.get_2d();
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let term = (1.0 - g * g) / (1.0 + g - 2.0 * g * u1);
+            utils::clamp((1.0 + g * g - term * term) / (2.0 * g), -1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+
+        let uvw = onb::OrthonormalBasis::build_from_w(&r_in.direction().unit_vector());
+        let direction = uvw.local(&ray::Vector::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        srec.scattered = ray::Ray::new(rec.point().clone(), direction, r_in.time().clone());
+        srec.attenuation = self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+        srec.pdf = self.phase(&cos_theta);
+        srec.is_specular = false;
+        return true;
+    }
+
+    fn scattering_pdf(
+        &self,
+        r_in: &ray::Ray,
+        _rec: &hittable::HitRecord,
+        scattered: &ray::Ray,
+    ) -> RayTracingFloat {
+        let cos_theta = vec3::dot(
+            &r_in.direction().unit_vector(),
+            &scattered.direction().unit_vector(),
+        );
+        return self.phase(&cos_theta);
+    }
+
+    fn albedo(&self, rec: &hittable::HitRecord) -> color::Color {
+        return self.albedo.value(&rec.u, &rec.v, &rec.p).clone();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
@@ -0,0 +1,38 @@
+use super::ray;
+use super::vec3;
+
+// Orthonormal basis, used to turn directions sampled around the z axis into directions around
+// an arbitrary vector.
+pub struct OrthonormalBasis {
+    axis: [ray::Vector; 3],
+}
+
+impl OrthonormalBasis {
+    pub fn build_from_w(n: &ray::Vector) -> Self {
+        let w = n.unit_vector();
+        let a = if w.x().abs() > 0.9 {
+            ray::Vector::new(0.0, 1.0, 0.0)
+        } else {
+            ray::Vector::new(1.0, 0.0, 0.0)
+        };
+        let v = vec3::cross(&w, &a).unit_vector();
+        let u = vec3::cross(&w, &v);
+        return Self { axis: [u, v, w] };
+    }
+
+    pub fn u(&self) -> &ray::Vector {
+        return &self.axis[0];
+    }
+
+    pub fn v(&self) -> &ray::Vector {
+        return &self.axis[1];
+    }
+
+    pub fn w(&self) -> &ray::Vector {
+        return &self.axis[2];
+    }
+
+    pub fn local(&self, a: &ray::Vector) -> ray::Vector {
+        return self.u() * a.x() + self.v() * a.y() + self.w() * a.z();
+    }
+}
@@ -18,41 +18,50 @@ impl vec3::Vec3<RayTracingFloat> {
         );
     }
 
-    pub fn random_in_unit_disk() -> Self {
-        loop {
-            let p = Self::new(
-                utils::random_double(&-1.0, &1.0),
-                utils::random_double(&-1.0, &1.0),
-                0.0,
-            );
-            if p.length_squared() >= 1.0 {
-                continue;
-            }
-            return p;
+    // Point in the unit disk in the xy plane, from a pair of sample dimensions. The concentric
+    // mapping keeps samples which are close in the square close in the disk.
+    pub fn sample_in_unit_disk(u: &(RayTracingFloat, RayTracingFloat)) -> Self {
+        let a = 2.0 * u.0 - 1.0;
+        let b = 2.0 * u.1 - 1.0;
+        if a == 0.0 && b == 0.0 {
+            return Self::zero();
         }
+        let quarter_pi = std::f64::consts::FRAC_PI_4;
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, quarter_pi * (b / a))
+        } else {
+            (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+        };
+        return Self::new(r * theta.cos(), r * theta.sin(), 0.0);
     }
 
-    pub fn random_in_unit_sphere() -> Self {
-        loop {
-            let p = Self::random(&-1.0, &1.0);
-            if p.length_squared() >= 1.0 {
-                continue;
-            }
-            return p;
-        }
+    // Direction distributed uniformly over the unit sphere.
+    pub fn sample_unit_vector(u: &(RayTracingFloat, RayTracingFloat)) -> Self {
+        let z = 1.0 - 2.0 * u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u.1;
+        return Self::new(r * phi.cos(), r * phi.sin(), z);
     }
 
-    pub fn random_unit_vector() -> Self {
-        return Self::random_in_unit_sphere().unit_vector();
+    // Point distributed uniformly within the unit sphere, from a pair of dimensions for the
+    // direction and one for the distance from the center.
+    pub fn sample_in_unit_sphere(
+        u: &(RayTracingFloat, RayTracingFloat),
+        w: &RayTracingFloat,
+    ) -> Self {
+        return Self::sample_unit_vector(u) * w.cbrt();
     }
 
-    pub fn random_in_hemisphere(normal: &Self) -> Self {
-        let in_unit_sphere = Self::random_in_unit_sphere();
-        if vec3::dot(&in_unit_sphere, normal) > 0.0 {
-            return in_unit_sphere;
-        } else {
-            return -in_unit_sphere;
-        }
+    // Direction around the z axis, distributed proportionally to its cosine with z.
+    pub fn sample_cosine_direction(u: &(RayTracingFloat, RayTracingFloat)) -> Self {
+        let (r1, r2) = *u;
+        let z = (1.0 - r2).sqrt();
+
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+
+        return Self::new(x, y, z);
     }
 
     pub fn near_zero(&self) -> bool {
@@ -76,6 +85,7 @@ pub struct Ray {
     orig: Point,
     dir: Vector,
     tm: RayTracingFloat,
+    random_key: u64, // for the random choices volumes make along the ray, see set_random_key
 }
 
 impl Ray {
@@ -88,6 +98,21 @@ impl Ray {
             orig: origin,
             dir: direction,
             tm: time,
+            random_key: 0,
+        };
+    }
+
+    pub fn new_with_key(
+        origin: Point,
+        direction: Vector,
+        time: RayTracingFloat,
+        random_key: u64,
+    ) -> Self {
+        return Ray {
+            orig: origin,
+            dir: direction,
+            tm: time,
+            random_key: random_key,
         };
     }
 
@@ -103,6 +128,16 @@ impl Ray {
         return &self.tm;
     }
 
+    pub fn random_key(&self) -> &u64 {
+        return &self.random_key;
+    }
+
+    // Volumes get no sampler, so the renderer draws random bits for each ray from the sampler of
+    // the path, which the volumes make their random choices with.
+    pub fn set_random_key(&mut self, key: &u64) {
+        self.random_key = *key;
+    }
+
     pub fn at(&self, t: &RayTracingFloat) -> Point {
         return self.origin() + self.direction() * t.clone();
     }
@@ -0,0 +1,616 @@
+use super::adaptive;
+use super::aov;
+use super::camera;
+use super::checkpoint;
+use super::color;
+use super::environment;
+use super::filter;
+use super::hittable;
+use super::hittable::Hittable;
+use super::material;
+use super::ray;
+use super::sampler;
+use super::utils::RayTracingFloat;
+
+// Edge length, in pixels, of the square tiles the image is split into.
+pub const TILE_SIZE: usize = 32;
+
+//
+// Tile
+//
+struct Tile {
+    x0: usize, // first column, inclusive
+    x1: usize, // last column, exclusive
+    y0: usize, // first row from the top of the image, inclusive
+    y1: usize, // last row from the top of the image, exclusive
+}
+
+impl Tile {
+    fn split_image(image_width: &usize, image_height: &usize) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y0 in (0..*image_height).step_by(TILE_SIZE) {
+            for x0 in (0..*image_width).step_by(TILE_SIZE) {
+                tiles.push(Tile {
+                    x0: x0,
+                    x1: (x0 + TILE_SIZE).min(*image_width),
+                    y0: y0,
+                    y1: (y0 + TILE_SIZE).min(*image_height),
+                });
+            }
+        }
+        return tiles;
+    }
+
+    fn width(&self) -> usize {
+        return self.x1 - self.x0;
+    }
+}
+
+// The light emitted towards a light sample's origin by the first surface the ray hits, less what
+// the volumes on the way take away. A ray that hits nothing brings no light: the environment is
+// counted by its own strategies.
+fn emitted_along(r: &ray::Ray, world: &dyn hittable::Hittable) -> color::Color {
+    let mut rec = hittable::HitRecord::new();
+    if !world.hit_surface(r, &0.001, &RayTracingFloat::INFINITY, &mut rec) {
+        return color::Color::zero();
+    }
+    let transmittance = world.transmittance(r, &0.001, &rec.t);
+    return rec.material().emitted(&rec.u, &rec.v, &rec.p) * transmittance;
+}
+
+// Random bits for a ray from the next dimension of the path's sample, see ray::Ray::set_random_key.
+fn random_key(sampler: &mut dyn sampler::Sampler) -> u64 {
+    return sampler::hash(&[sampler.get_1d().to_bits()]);
+}
+
+//
+// MIS Heuristic
+//
+// How a sample taken with one of two strategies is weighted against the other strategy, given the
+// densities with which both would have picked it (Veach, "Optimally Combining Sampling Techniques
+// for Monte Carlo Rendering", 1995). The weights of both strategies always add up to one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MisHeuristic {
+    Balance,
+    Power, // with an exponent of 2
+}
+
+impl MisHeuristic {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "balance" => Some(MisHeuristic::Balance),
+            "power" => Some(MisHeuristic::Power),
+            _ => None,
+        };
+    }
+
+    // Weight of a sample taken with density pdf_a, when the other strategy has density pdf_b.
+    pub fn weight(&self, pdf_a: &RayTracingFloat, pdf_b: &RayTracingFloat) -> RayTracingFloat {
+        if *pdf_a <= 0.0 {
+            return 0.0;
+        }
+        return match self {
+            MisHeuristic::Balance => pdf_a / (pdf_a + pdf_b),
+            MisHeuristic::Power => (pdf_a * pdf_a) / (pdf_a * pdf_a + pdf_b * pdf_b),
+        };
+    }
+}
+
+pub const DEFAULT_MIS_HEURISTIC: MisHeuristic = MisHeuristic::Power;
+
+// Number of bounces before paths may be terminated by Russian roulette.
+pub const DEFAULT_RR_MIN_DEPTH: u32 = 3;
+
+// Highest chance of a path surviving Russian roulette.
+const RR_MAX_SURVIVAL: RayTracingFloat = 0.95;
+
+// Gathers the light arriving along a ray. Given an AOV sample, what the ray hits first is recorded
+// in it as well.
+//
+// The path is followed bounce by bounce, carrying its throughput: the fraction of the light found
+// further along that makes it back to the camera. At every non-specular surface, light is gathered
+// with two strategies: a point on one of the lights and a direction towards the environment are
+// sampled and the light arriving from them is added directly (next event estimation), and the
+// scattered ray picks up light wherever it hits an emitter or escapes into the environment. Both
+// are weighted with multiple importance sampling, so each direction gets counted once, by
+// whichever strategy is likely to find it.
+//
+// After rr_min_depth bounces, paths are randomly terminated with a probability that grows as their
+// throughput drops (Russian roulette). Surviving paths are scaled up to make up for the ones that
+// were stopped, which keeps the estimate unbiased.
+pub fn ray_color(
+    r: &ray::Ray,
+    environment: &dyn environment::Environment,
+    world: &dyn hittable::Hittable,
+    lights: &hittable::HittableList,
+    mis: &MisHeuristic,
+    max_depth: &u32,
+    rr_min_depth: &u32,
+    sampler: &mut dyn sampler::Sampler,
+    mut aov: Option<&mut aov::AovSample>,
+) -> color::Color {
+    let mut radiance = color::Color::zero();
+    let mut throughput = color::Color::new(1.0, 1.0, 1.0);
+    let mut ray = ray::Ray::new(r.origin().clone(), r.direction().clone(), r.time().clone());
+    // Weights of the light the ray itself finds on emitters and in the environment: 1 for camera
+    // rays and specular bounces, which light sampling cannot reproduce.
+    let mut emission_weight = 1.0;
+    let mut environment_weight = 1.0;
+    let sample_environment = environment.emits();
+
+    for bounce in 0..*max_depth {
+        ray.set_random_key(&random_key(sampler));
+
+        // If the ray hits nothing, add the light of the environment.
+        let mut rec = hittable::HitRecord::new();
+        if !world.hit(&ray, &0.001, &RayTracingFloat::INFINITY, &mut rec) {
+            let background = environment.radiance(ray.direction());
+            if let Some(aov) = aov.take() {
+                aov.record_miss(&background);
+            }
+            radiance += &throughput * background * environment_weight;
+            break;
+        }
+        if let Some(aov) = aov.take() {
+            aov.record_hit(&ray, &rec);
+        }
+
+        radiance += &throughput * rec.material().emitted(&rec.u, &rec.v, &rec.p) * emission_weight;
+
+        let mut srec = material::ScatterRecord::new();
+        if !rec.material().scatter(&ray, &rec, &mut srec, sampler) {
+            break;
+        }
+
+        if srec.is_specular {
+            throughput = throughput * &srec.attenuation;
+            emission_weight = 1.0;
+            environment_weight = 1.0;
+        } else {
+            if srec.pdf <= 0.0 {
+                break;
+            }
+
+            // Direct light, which like the scattered ray is one bounce further along the path.
+            let sample_lights = !lights.get_objects().is_empty() && bounce + 1 < *max_depth;
+            if sample_lights {
+                let to_light = ray::Ray::new_with_key(
+                    rec.point().clone(),
+                    lights.random(rec.point(), sampler),
+                    ray.time().clone(),
+                    random_key(sampler),
+                );
+                let light_pdf = lights.pdf_value(rec.point(), to_light.direction());
+                let scattering_pdf = rec.material().scattering_pdf(&ray, &rec, &to_light);
+                if light_pdf > 0.0 && light_pdf.is_finite() && scattering_pdf > 0.0 {
+                    let bsdf_pdf = rec.material().sampling_pdf(&ray, &rec, &to_light);
+                    let weight = mis.weight(&light_pdf, &bsdf_pdf);
+                    radiance += &throughput
+                        * &srec.attenuation
+                        * emitted_along(&to_light, world)
+                        * (scattering_pdf * weight / light_pdf);
+                }
+            }
+
+            let sample_environment = sample_environment && bounce + 1 < *max_depth;
+            if sample_environment {
+                let to_environment = ray::Ray::new_with_key(
+                    rec.point().clone(),
+                    environment.sample_direction(&sampler.get_2d()),
+                    ray.time().clone(),
+                    random_key(sampler),
+                );
+                let environment_pdf = environment.pdf(to_environment.direction());
+                let scattering_pdf = rec.material().scattering_pdf(&ray, &rec, &to_environment);
+                let mut shadow_rec = hittable::HitRecord::new();
+                if environment_pdf > 0.0
+                    && scattering_pdf > 0.0
+                    && !world.hit_surface(
+                        &to_environment,
+                        &0.001,
+                        &RayTracingFloat::INFINITY,
+                        &mut shadow_rec,
+                    )
+                {
+                    let bsdf_pdf = rec.material().sampling_pdf(&ray, &rec, &to_environment);
+                    let weight = mis.weight(&environment_pdf, &bsdf_pdf);
+                    let transmittance =
+                        world.transmittance(&to_environment, &0.001, &RayTracingFloat::INFINITY);
+                    radiance += &throughput
+                        * &srec.attenuation
+                        * environment.radiance(to_environment.direction())
+                        * (scattering_pdf * weight * transmittance / environment_pdf);
+                }
+            }
+
+            // The scattered ray either hits an emitter, which only light sampling could have
+            // found as well, or escapes into the environment.
+            let light_pdf = if sample_lights {
+                lights.pdf_value(rec.point(), srec.scattered.direction())
+            } else {
+                0.0
+            };
+            emission_weight = mis.weight(&srec.pdf, &light_pdf);
+            let environment_pdf = if sample_environment {
+                environment.pdf(srec.scattered.direction())
+            } else {
+                0.0
+            };
+            environment_weight = mis.weight(&srec.pdf, &environment_pdf);
+
+            // Monte Carlo estimate of the scattered light: BSDF * cosine * incoming / pdf.
+            let scattering_pdf = rec.material().scattering_pdf(&ray, &rec, &srec.scattered);
+            if scattering_pdf <= 0.0 {
+                break;
+            }
+            throughput = throughput * &srec.attenuation * (scattering_pdf / srec.pdf);
+        }
+        ray = srec.scattered;
+
+        if bounce + 1 >= *rr_min_depth {
+            let brightest = throughput.x().max(throughput.y()).max(throughput.z());
+            // Even bright paths are stopped now and then, so that paths bouncing between mirrors
+            // come to an end.
+            let survival = brightest.min(RR_MAX_SURVIVAL);
+            if survival <= 0.0 || sampler.get_1d() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+    }
+
+    return radiance;
+}
+
+//
+// Accumulation
+//
+// Everything gathered for a pixel so far. Progressive renders keep adding to it pass by pass.
+#[derive(Clone)]
+pub struct PixelAccumulator {
+    pub sum: color::Color, // of the filter weighted samples
+    pub samples: usize,
+    pub estimate: adaptive::ErrorEstimate,
+    pub aov: aov::AovPixel,
+}
+
+impl PixelAccumulator {
+    pub fn new() -> Self {
+        return Self {
+            sum: color::Color::zero(),
+            samples: 0,
+            estimate: adaptive::ErrorEstimate::new(),
+            aov: aov::AovPixel::new(),
+        };
+    }
+}
+
+// The state of a render in progress, as stored in checkpoints. Along with the pixels, it keeps
+// the settings that decide which samples get taken, so that a resumed render continues with the
+// very samples the uninterrupted render would have taken.
+pub struct Accumulation {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub seed: u64,
+    pub sampler: String,
+    pub pass_samples: usize, // samples per pixel added by each pass
+    pub collect_aovs: bool,
+    pub fingerprint: u64, // of the scene and the other settings the pixels depend on
+    pub target_samples: usize, // samples per pixel the passes so far went up to
+    pub pixels: Vec<PixelAccumulator>, // row by row from the top of the image
+}
+
+impl Accumulation {
+    pub fn new(
+        image_width: &usize,
+        image_height: &usize,
+        seed: &u64,
+        sampler: &str,
+        pass_samples: &usize,
+        collect_aovs: &bool,
+        fingerprint: &u64,
+    ) -> Self {
+        return Self {
+            image_width: *image_width,
+            image_height: *image_height,
+            seed: *seed,
+            sampler: String::from(sampler),
+            pass_samples: (*pass_samples).max(1),
+            collect_aovs: *collect_aovs,
+            fingerprint: *fingerprint,
+            target_samples: 0,
+            pixels: vec![PixelAccumulator::new(); image_width * image_height],
+        };
+    }
+
+    // Checks that a render with the given settings can carry on from this one.
+    pub fn check_resume(
+        &self,
+        image_width: &usize,
+        image_height: &usize,
+        seed: &u64,
+        sampler: &str,
+        pass_samples: &Option<usize>,
+        collect_aovs: &bool,
+        fingerprint: &u64,
+    ) -> Result<(), String> {
+        if self.image_width != *image_width || self.image_height != *image_height {
+            return Err(format!(
+                "checkpoint is {}x{} but the image is {}x{}",
+                self.image_width, self.image_height, image_width, image_height
+            ));
+        }
+        if self.seed != *seed {
+            return Err(format!(
+                "checkpoint was rendered with seed {} instead of {}",
+                self.seed, seed
+            ));
+        }
+        if self.sampler != sampler {
+            return Err(format!(
+                "checkpoint was rendered with the '{}' sampler instead of '{}'",
+                self.sampler, sampler
+            ));
+        }
+        if pass_samples.is_some_and(|samples| samples != self.pass_samples) {
+            return Err(format!(
+                "checkpoint was rendered in passes of {} samples per pixel",
+                self.pass_samples
+            ));
+        }
+        if *collect_aovs && !self.collect_aovs {
+            return Err(String::from(
+                "checkpoint has no auxiliary passes, which the output and denoiser need",
+            ));
+        }
+        if self.fingerprint != *fingerprint {
+            return Err(String::from(
+                "checkpoint was rendered from another scene or with other render settings",
+            ));
+        }
+        return Ok(());
+    }
+
+    // Averages what has been accumulated so far into an image.
+    pub fn result(&self) -> RenderResult {
+        return RenderResult {
+            pixels: self
+                .pixels
+                .iter()
+                .map(|pixel| &pixel.sum / pixel.samples.max(1) as RayTracingFloat)
+                .collect(),
+            aovs: if self.collect_aovs {
+                self.pixels.iter().map(|pixel| pixel.aov.clone()).collect()
+            } else {
+                Vec::new()
+            },
+        };
+    }
+}
+
+// Adds samples to the pixels of a tile, until each has the target number of samples or, with
+// adaptive sampling, is found to be done.
+fn render_tile(
+    tile: &Tile,
+    pixels: &mut [PixelAccumulator],
+    target_samples: &usize,
+    image_width: &usize,
+    image_height: &usize,
+    cam: &camera::Camera,
+    sampler: &mut dyn sampler::Sampler,
+    filter: &filter::FilterSampler,
+    max_depth: &u32,
+    rr_min_depth: &u32,
+    world: &dyn hittable::Hittable,
+    lights: &hittable::HittableList,
+    mis: &MisHeuristic,
+    environment: &dyn environment::Environment,
+    collect_aovs: &bool,
+    adaptive: &Option<adaptive::AdaptiveSettings>,
+) {
+    for y in tile.y0..tile.y1 {
+        // Camera space has v pointing up, while image rows are stored top to bottom.
+        let j = image_height - 1 - y;
+        for i in tile.x0..tile.x1 {
+            let pixel = &mut pixels[(y - tile.y0) * tile.width() + (i - tile.x0)];
+            while pixel.samples < *target_samples {
+                // With adaptive sampling, the samples are taken in batches with an error check
+                // before each. Otherwise they are all taken at once.
+                let batch_end = match adaptive {
+                    Some(settings) => {
+                        if pixel.samples >= settings.min_samples
+                            && pixel.estimate.error() < settings.threshold
+                        {
+                            break;
+                        }
+                        let batches = pixel.samples / settings.min_samples + 1;
+                        (batches * settings.min_samples).min(*target_samples)
+                    }
+                    None => *target_samples,
+                };
+                for s in pixel.samples..batch_end {
+                    sampler.start_pixel_sample(&i, &y, &s);
+                    // Offset from the pixel center, drawn following the reconstruction filter.
+                    let (pixel_u, pixel_v) = sampler.get_2d();
+                    let (offset_u, weight_u) = filter.sample(&pixel_u);
+                    let (offset_v, weight_v) = filter.sample(&pixel_v);
+                    // Pixel i covers [i, i + 1) / width of the image, so any size maps evenly.
+                    let u =
+                        (i as RayTracingFloat + 0.5 + offset_u) / *image_width as RayTracingFloat;
+                    let v =
+                        (j as RayTracingFloat + 0.5 + offset_v) / *image_height as RayTracingFloat;
+                    let r = cam.get_ray(&u, &v, sampler);
+                    let mut aov_sample = aov::AovSample::new();
+                    let radiance = ray_color(
+                        &r,
+                        environment,
+                        world,
+                        lights,
+                        mis,
+                        max_depth,
+                        rr_min_depth,
+                        sampler,
+                        if *collect_aovs {
+                            Some(&mut aov_sample)
+                        } else {
+                            None
+                        },
+                    );
+                    let sample = radiance * (weight_u * weight_v);
+                    pixel.estimate.add(&sample);
+                    pixel.sum += sample;
+                    if *collect_aovs {
+                        pixel.aov.add(&aov_sample);
+                    }
+                }
+                pixel.samples = batch_end;
+            }
+        }
+    }
+}
+
+// The result of a render: linear radiance, plus what the camera rays hit first if requested. Both
+// are stored row by row from the top of the image.
+pub struct RenderResult {
+    pub pixels: Vec<color::Color>,
+    pub aovs: Vec<aov::AovPixel>,
+}
+
+// Samples per pixel of each pass when rendering for a time limit, unless given otherwise.
+pub const DEFAULT_TIME_LIMIT_PASS_SAMPLES: usize = 16;
+
+// Renders in passes, each adding up to the accumulation's pass samples to every pixel, until the
+// pixels have the given samples per pixel or the deadline has passed. A pass cut short by the
+// deadline leaves the tiles it did not get to with fewer samples, which the per pixel sample
+// counts account for. Only the first pass is always finished, so that no pixel is left empty.
+// After each pass the accumulation is written to the checkpoint, if there is one.
+pub fn render(
+    accumulation: &mut Accumulation,
+    cam: &camera::Camera,
+    samples_per_pixel: &usize,
+    filter: &filter::FilterSampler,
+    max_depth: &u32,
+    rr_min_depth: &u32,
+    world: &dyn hittable::Hittable,
+    lights: &hittable::HittableList,
+    mis: &MisHeuristic,
+    environment: &dyn environment::Environment,
+    num_threads: &usize,
+    adaptive: &Option<adaptive::AdaptiveSettings>,
+    deadline: &Option<std::time::Instant>,
+    checkpoint: &Option<String>,
+) {
+    let out_of_time = || deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline);
+    let image_width = accumulation.image_width;
+    let image_height = accumulation.image_height;
+    let sampler_type = sampler::find_sampler(&accumulation.sampler).unwrap();
+    let (seed, pass_samples) = (accumulation.seed, accumulation.pass_samples);
+    let collect_aovs = accumulation.collect_aovs;
+    let tiles = Tile::split_image(&image_width, &image_height);
+
+    let mut passes = 0;
+    while accumulation.target_samples < *samples_per_pixel {
+        if accumulation.target_samples > 0 && out_of_time() {
+            break;
+        }
+        let may_stop = accumulation.target_samples > 0;
+        let target_samples = (accumulation.target_samples + pass_samples).min(*samples_per_pixel);
+        println!("Rendering up to {} samples per pixel", target_samples);
+
+        // The image is kept as linear radiance, it is only quantized when written out.
+        let buffer = std::sync::Mutex::new(std::mem::take(&mut accumulation.pixels));
+        let next_tile = std::sync::atomic::AtomicUsize::new(0);
+        let tiles_done = std::sync::atomic::AtomicUsize::new(0);
+
+        // Worker threads pull tiles off a shared counter until every tile has been claimed, so
+        // threads that draw cheap tiles (e.g. empty background) simply go on to take more.
+        std::thread::scope(|scope| {
+            for _ in 0..(*num_threads).max(1) {
+                scope.spawn(|| {
+                    let mut sampler = (sampler_type.build)(&pass_samples, &seed);
+                    loop {
+                        let tile_index =
+                            next_tile.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        if tile_index >= tiles.len() || (may_stop && out_of_time()) {
+                            break;
+                        }
+
+                        // Work on a copy of the tile's pixels, so that other threads can get at
+                        // theirs in the meantime.
+                        let tile = &tiles[tile_index];
+                        let mut pixels = Vec::with_capacity(tile.width() * (tile.y1 - tile.y0));
+                        let buffer_pixels = buffer.lock().unwrap();
+                        for y in tile.y0..tile.y1 {
+                            let row_offset = y * image_width + tile.x0;
+                            pixels.extend_from_slice(
+                                &buffer_pixels[row_offset..row_offset + tile.width()],
+                            );
+                        }
+                        drop(buffer_pixels);
+
+                        render_tile(
+                            tile,
+                            &mut pixels,
+                            &target_samples,
+                            &image_width,
+                            &image_height,
+                            cam,
+                            sampler.as_mut(),
+                            filter,
+                            max_depth,
+                            rr_min_depth,
+                            world,
+                            lights,
+                            mis,
+                            environment,
+                            &collect_aovs,
+                            adaptive,
+                        );
+
+                        let mut buffer_pixels = buffer.lock().unwrap();
+                        for (row, tile_row) in pixels.chunks(tile.width()).enumerate() {
+                            let row_offset = (tile.y0 + row) * image_width + tile.x0;
+                            buffer_pixels[row_offset..row_offset + tile.width()]
+                                .clone_from_slice(tile_row);
+                        }
+                        drop(buffer_pixels);
+
+                        let done =
+                            tiles_done.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                        println!("Tiles remaining: {}", tiles.len() - done);
+                    }
+                });
+            }
+        });
+        accumulation.pixels = buffer.into_inner().unwrap();
+        passes += 1;
+        // A pass cut short keeps its old target, so that resuming finishes the tiles it missed.
+        if tiles_done.into_inner() == tiles.len() {
+            accumulation.target_samples = target_samples;
+        }
+
+        if let Some(path) = checkpoint {
+            // A failed checkpoint only costs the ability to resume, so the render carries on.
+            match checkpoint::save(path, accumulation) {
+                Ok(_) => println!("Wrote checkpoint {}", path),
+                Err(err) => eprintln!("warning: {}", err),
+            }
+        }
+    }
+    println!("Done");
+
+    if accumulation.target_samples < *samples_per_pixel && out_of_time() {
+        println!("Time limit reached after {} passes", passes);
+    }
+    if adaptive.is_some() || deadline.is_some() {
+        let total = accumulation
+            .pixels
+            .iter()
+            .map(|pixel| pixel.samples)
+            .sum::<usize>();
+        println!(
+            "Average samples per pixel: {:.1}",
+            total as RayTracingFloat / accumulation.pixels.len().max(1) as RayTracingFloat
+        );
+    }
+}
@@ -0,0 +1,396 @@
+// Samplers hand out the numbers in [0, 1) that the random choices of a path are made with: where
+// within the pixel the camera ray goes, where on the lens and when it starts, which way it scatters
+// and so on. Each choice takes the next dimension of the current pixel sample, so that samplers
+// can spread the samples of a pixel evenly over every dimension, which makes for less noise than
+// independent random numbers at the same sample count.
+//
+// All samplers are deterministic: a dimension's value only depends on the seed, the pixel, the
+// sample index and the dimension, not on which thread takes it or in what order.
+use super::utils::RayTracingFloat;
+
+pub trait Sampler {
+    // Starts the given sample of a pixel, the next dimension handed out is the first one.
+    fn start_pixel_sample(&mut self, x: &usize, y: &usize, sample_index: &usize);
+
+    // Returns the next dimension of the current sample.
+    fn get_1d(&mut self) -> RayTracingFloat;
+
+    // Returns the next two dimensions of the current sample, spread out as a pair.
+    fn get_2d(&mut self) -> (RayTracingFloat, RayTracingFloat);
+}
+
+// Largest float below 1.
+const ONE_MINUS_EPSILON: RayTracingFloat = 1.0 - RayTracingFloat::EPSILON / 2.0;
+
+// Mixes the bits of a 64-bit value, with the finalizer of MurmurHash3.
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xff51afd7ed558ccd);
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xc4ceb9fe1a85ec53);
+    v ^= v >> 33;
+    return v;
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    let mut h = 0x9e3779b97f4a7c15;
+    for v in values {
+        h = mix_bits(h ^ mix_bits(v.wrapping_add(0x9e3779b97f4a7c15)));
+    }
+    return h;
+}
+
+// Maps random bits to [0, 1).
+fn to_unit(bits: &u64) -> RayTracingFloat {
+    return (bits >> 11) as RayTracingFloat / (1u64 << 53) as RayTracingFloat;
+}
+
+// A number in [0, 1) that only depends on the given values, for the random choices made where no
+// sampler is at hand, like how far a ray gets into a volume with the random key of the ray.
+pub fn hash_to_unit(values: &[u64]) -> RayTracingFloat {
+    return to_unit(&hash(values));
+}
+
+// Element i of a random permutation of [0, n), chosen by seed, without storing the permutation.
+// From Andrew Kensler, "Correlated Multi-Jittered Sampling" (2013).
+fn permutation_element(i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        // Values outside [0, n) are permuted again until they land inside.
+        if i < n {
+            break;
+        }
+    }
+    return ((i as u64 + seed as u64) % n as u64) as u32;
+}
+
+// The pixel and sample currently handed out, shared by all samplers.
+struct PixelSample {
+    seed: u64,
+    pixel_seed: u64,
+    index: u64,
+    dimension: u64,
+}
+
+impl PixelSample {
+    fn new(seed: &u64) -> Self {
+        return Self {
+            seed: *seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        };
+    }
+
+    fn start(&mut self, x: &usize, y: &usize, sample_index: &usize) {
+        self.pixel_seed = hash(&[self.seed, *x as u64, *y as u64]);
+        self.index = *sample_index as u64;
+        self.dimension = 0;
+    }
+
+    // Takes the next dimension.
+    fn next_dimension(&mut self) -> u64 {
+        self.dimension += 1;
+        return self.dimension - 1;
+    }
+
+    // Random value for a dimension of the current sample, independent of all others.
+    fn random(&self, dimension: &u64) -> RayTracingFloat {
+        return to_unit(&hash(&[self.pixel_seed, self.index, *dimension]));
+    }
+}
+
+//
+// Independent
+//
+// Independent uniform random numbers.
+pub struct IndependentSampler {
+    sample: PixelSample,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: &u64) -> Self {
+        return Self {
+            sample: PixelSample::new(seed),
+        };
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: &usize, y: &usize, sample_index: &usize) {
+        self.sample.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> RayTracingFloat {
+        let dimension = self.sample.next_dimension();
+        return self.sample.random(&dimension);
+    }
+
+    fn get_2d(&mut self) -> (RayTracingFloat, RayTracingFloat) {
+        return (self.get_1d(), self.get_1d());
+    }
+}
+
+//
+// Stratified
+//
+// Splits every dimension into as many strata as there are samples per pixel (pairs of dimensions
+// into a grid of about that many cells) and puts one jittered sample into each. The strata are
+// visited in a different random order for every pixel and dimension.
+//
+// Renders made in passes stratify the samples of each pass on their own, as the total number of
+// samples may not be known yet, and a resumed render has to take the same samples as one that
+// was never interrupted.
+pub struct StratifiedSampler {
+    sample: PixelSample,
+    samples_per_pixel: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: &usize, seed: &u64) -> Self {
+        return Self {
+            sample: PixelSample::new(seed),
+            samples_per_pixel: (*samples_per_pixel).max(1) as u64,
+        };
+    }
+
+    // The stratum of the current sample, out of the given number.
+    fn stratum(&self, dimension: &u64, strata: &u64) -> u64 {
+        // Samples beyond the first round of strata go through them again in another order.
+        let round = self.sample.index / strata;
+        let seed = hash(&[self.sample.pixel_seed, *dimension, round]);
+        return permutation_element(
+            (self.sample.index % strata) as u32,
+            *strata as u32,
+            seed as u32,
+        ) as u64;
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: &usize, y: &usize, sample_index: &usize) {
+        self.sample.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> RayTracingFloat {
+        let dimension = self.sample.next_dimension();
+        let stratum = self.stratum(&dimension, &self.samples_per_pixel);
+        return (stratum as RayTracingFloat + self.sample.random(&dimension))
+            / self.samples_per_pixel as RayTracingFloat;
+    }
+
+    fn get_2d(&mut self) -> (RayTracingFloat, RayTracingFloat) {
+        let dimension = self.sample.next_dimension();
+        let dimension_y = self.sample.next_dimension();
+        let columns = ((self.samples_per_pixel as RayTracingFloat).sqrt() as u64).max(1);
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let stratum = self.stratum(&dimension, &(columns * rows));
+        return (
+            ((stratum % columns) as RayTracingFloat + self.sample.random(&dimension))
+                / columns as RayTracingFloat,
+            ((stratum / columns) as RayTracingFloat + self.sample.random(&dimension_y))
+                / rows as RayTracingFloat,
+        );
+    }
+}
+
+//
+// Halton
+//
+// The Halton sequence, with the radical inverse in the n-th prime base as the n-th dimension.
+// The digits are Owen scrambled with seeds of each pixel's own, which keeps neighboring pixels from
+// sharing the same pattern and spreads out the higher dimensions, whose large bases would leave
+// the first few samples bunched together. Dimensions beyond the table of primes are independent
+// random numbers.
+pub struct HaltonSampler {
+    sample: PixelSample,
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// Mirrors the digits of a in the given base around the decimal point, permuting every digit
+// depending on the ones before it. The zeros beyond the last digit of a are permuted as well,
+// up to the precision of a float.
+fn owen_scrambled_radical_inverse(base: &u64, a: &u64, seed: &u64) -> RayTracingFloat {
+    let inverse_base = 1.0 / *base as RayTracingFloat;
+    let mut inverse_base_n: RayTracingFloat = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let mut a = *a;
+    while 1.0 - inverse_base_n < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_seed = mix_bits(seed ^ reversed_digits);
+        let digit = permutation_element(digit as u32, *base as u32, digit_seed as u32) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_n *= inverse_base;
+        a = next;
+    }
+    return (reversed_digits as RayTracingFloat * inverse_base_n).min(ONE_MINUS_EPSILON);
+}
+
+impl HaltonSampler {
+    pub fn new(seed: &u64) -> Self {
+        return Self {
+            sample: PixelSample::new(seed),
+        };
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: &usize, y: &usize, sample_index: &usize) {
+        self.sample.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> RayTracingFloat {
+        let dimension = self.sample.next_dimension();
+        if dimension >= PRIMES.len() as u64 {
+            return self.sample.random(&dimension);
+        }
+        return owen_scrambled_radical_inverse(
+            &PRIMES[dimension as usize],
+            &self.sample.index,
+            &hash(&[self.sample.pixel_seed, dimension]),
+        );
+    }
+
+    fn get_2d(&mut self) -> (RayTracingFloat, RayTracingFloat) {
+        return (self.get_1d(), self.get_1d());
+    }
+}
+
+//
+// Sobol
+//
+// The first two dimensions of the Sobol sequence with Owen scrambling, after Brent Burley,
+// "Practical Hash-based Owen Scrambling" (JCGT 2020). Every request takes a fresh pair of
+// dimensions: the order of the samples is shuffled and the points are scrambled with seeds of its
+// own, which keeps the pairs of a pixel from being correlated with each other.
+pub struct SobolSampler {
+    sample: PixelSample,
+}
+
+fn sobol_dimension_0(index: &u32) -> u32 {
+    return index.reverse_bits();
+}
+
+fn sobol_dimension_1(index: &u32) -> u32 {
+    let mut result = 0;
+    let mut direction: u32 = 1 << 31;
+    let mut index = *index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    return result;
+}
+
+// A hash which only lets bits affect the bits above them.
+fn laine_karras_permutation(x: &u32, seed: &u32) -> u32 {
+    let mut x = x.wrapping_add(*seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    return x;
+}
+
+// Owen scrambling: every bit is flipped depending on the bits above it.
+fn nested_uniform_scramble(x: &u32, seed: &u32) -> u32 {
+    return laine_karras_permutation(&x.reverse_bits(), seed).reverse_bits();
+}
+
+impl SobolSampler {
+    pub fn new(seed: &u64) -> Self {
+        return Self {
+            sample: PixelSample::new(seed),
+        };
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: &usize, y: &usize, sample_index: &usize) {
+        self.sample.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> RayTracingFloat {
+        return self.get_2d().0;
+    }
+
+    fn get_2d(&mut self) -> (RayTracingFloat, RayTracingFloat) {
+        let dimension = self.sample.next_dimension();
+        let seed = hash(&[self.sample.pixel_seed, dimension]);
+        let index = nested_uniform_scramble(&(self.sample.index as u32), &(seed as u32));
+        let x = nested_uniform_scramble(&sobol_dimension_0(&index), &((seed >> 32) as u32));
+        let y = nested_uniform_scramble(&sobol_dimension_1(&index), &(mix_bits(seed) as u32));
+        let scale = 1.0 / (1u64 << 32) as RayTracingFloat;
+        return (x as RayTracingFloat * scale, y as RayTracingFloat * scale);
+    }
+}
+
+//
+// Sampler Types
+//
+pub struct SamplerType {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub build: fn(&usize, &u64) -> Box<dyn Sampler>, // from samples per pixel and seed
+}
+
+pub const SAMPLERS: &[SamplerType] = &[
+    SamplerType {
+        name: "independent",
+        description: "independent uniform random numbers",
+        build: |_samples_per_pixel, seed| Box::new(IndependentSampler::new(seed)),
+    },
+    SamplerType {
+        name: "stratified",
+        description: "one jittered sample in each stratum, per pass",
+        build: |samples_per_pixel, seed| Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+    },
+    SamplerType {
+        name: "halton",
+        description: "Owen scrambled Halton sequence",
+        build: |_samples_per_pixel, seed| Box::new(HaltonSampler::new(seed)),
+    },
+    SamplerType {
+        name: "sobol",
+        description: "Owen scrambled Sobol sequence",
+        build: |_samples_per_pixel, seed| Box::new(SobolSampler::new(seed)),
+    },
+];
+
+pub const DEFAULT_SAMPLER: &str = "sobol";
+
+pub fn find_sampler(name: &str) -> Option<&'static SamplerType> {
+    return SAMPLERS.iter().find(|sampler| sampler.name == name);
+}
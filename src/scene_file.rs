@@ -0,0 +1,973 @@
+// Loader for the text scene description format documented in docs/scene-format.md.
+//
+// A scene file is a sequence of statements. Every statement starts with a name, is followed by
+// any number of arguments and optionally by a block of nested statements in braces. Statements
+// end at a newline or a ';'. Parsing happens in two steps: the text is first turned into a tree
+// of generic statements, which is then interpreted into a scenes::Scene.
+use super::camera;
+use super::color;
+use super::density;
+use super::environment;
+use super::hittable;
+use super::hittable_box;
+use super::hittable_sphere;
+use super::hittable_volume;
+use super::material;
+use super::ray;
+use super::scenes;
+use super::texture;
+use super::utils::RayTracingFloat;
+use std::convert::TryFrom;
+
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}:{}: {}", self.line, self.column, self.message);
+    }
+}
+
+//
+// Tokenizer
+//
+#[derive(Clone, Copy, PartialEq)]
+enum TokenKind {
+    Word,
+    String,
+    OpenBrace,
+    CloseBrace,
+    EndOfStatement,
+    EndOfFile,
+}
+
+#[derive(Clone)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> ParseError {
+        return ParseError {
+            line: self.line,
+            column: self.column,
+            message: message,
+        };
+    }
+
+    fn describe(&self) -> String {
+        return match self.kind {
+            TokenKind::Word => format!("'{}'", self.text),
+            TokenKind::String => format!("\"{}\"", self.text),
+            TokenKind::OpenBrace => String::from("'{'"),
+            TokenKind::CloseBrace => String::from("'}'"),
+            TokenKind::EndOfStatement => String::from("end of statement"),
+            TokenKind::EndOfFile => String::from("end of file"),
+        };
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    return !c.is_whitespace() && !matches!(c, '{' | '}' | ';' | '#' | '"');
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut push = |kind: TokenKind, text: String| {
+            tokens.push(Token {
+                kind: kind,
+                text: text,
+                line: start_line,
+                column: start_column,
+            });
+        };
+
+        if c == '\n' {
+            chars.next();
+            push(TokenKind::EndOfStatement, String::from("\n"));
+            line += 1;
+            column = 1;
+            continue;
+        }
+
+        chars.next();
+        column += 1;
+        match c {
+            '{' => push(TokenKind::OpenBrace, c.to_string()),
+            '}' => push(TokenKind::CloseBrace, c.to_string()),
+            ';' => push(TokenKind::EndOfStatement, c.to_string()),
+            '#' => {
+                // Comments run up to, but not including, the end of the line.
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                    column += 1;
+                }
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.peek(), Some('"') | Some('\\')) => {
+                            text.push(chars.next().unwrap());
+                            column += 2;
+                        }
+                        Some('\n') | None => {
+                            return Err(ParseError {
+                                line: start_line,
+                                column: start_column,
+                                message: String::from("unterminated string"),
+                            });
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            column += 1;
+                        }
+                    }
+                }
+                column += 1;
+                push(TokenKind::String, text);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut text = c.to_string();
+                while let Some(&c) = chars.peek().filter(|&&c| is_word_char(c)) {
+                    text.push(c);
+                    chars.next();
+                    column += 1;
+                }
+                push(TokenKind::Word, text);
+            }
+        }
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::EndOfFile,
+        text: String::new(),
+        line: line,
+        column: column,
+    });
+    return Ok(tokens);
+}
+
+//
+// Statement Tree
+//
+struct Statement {
+    name: Token,
+    args: Vec<Token>,
+    block: Option<Vec<Statement>>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        return &self.tokens[self.pos];
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::EndOfFile {
+            self.pos += 1;
+        }
+        return token;
+    }
+
+    // Parses statements up to the end of the file, or up to and including the '}' closing the
+    // block when `in_block` is set.
+    fn statements(&mut self, in_block: bool) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = Vec::new();
+        loop {
+            let token = self.next();
+            match token.kind {
+                TokenKind::EndOfStatement => continue,
+                TokenKind::EndOfFile if in_block => {
+                    return Err(token.error(String::from("expected '}' before end of file")));
+                }
+                TokenKind::EndOfFile => return Ok(statements),
+                TokenKind::CloseBrace if in_block => return Ok(statements),
+                TokenKind::Word => statements.push(self.statement(token)?),
+                _ => {
+                    return Err(
+                        token.error(format!("expected a statement, found {}", token.describe()))
+                    );
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, name: Token) -> Result<Statement, ParseError> {
+        let mut args = Vec::new();
+        while matches!(self.peek().kind, TokenKind::Word | TokenKind::String) {
+            args.push(self.next());
+        }
+
+        let mut block = None;
+        if self.peek().kind == TokenKind::OpenBrace {
+            self.next();
+            block = Some(self.statements(true)?);
+        }
+
+        match self.peek().kind {
+            TokenKind::EndOfStatement | TokenKind::EndOfFile | TokenKind::CloseBrace => {}
+            _ => {
+                let token = self.peek();
+                return Err(token.error(format!(
+                    "expected end of statement, found {}",
+                    token.describe()
+                )));
+            }
+        }
+
+        return Ok(Statement {
+            name: name,
+            args: args,
+            block: block,
+        });
+    }
+}
+
+//
+// Statement Accessors
+//
+impl Statement {
+    fn error(&self, message: String) -> ParseError {
+        return self.name.error(message);
+    }
+
+    fn expect_no_block(&self) -> Result<(), ParseError> {
+        if self.block.is_some() {
+            return Err(self.error(format!("'{}' does not take a block", self.name.text)));
+        }
+        return Ok(());
+    }
+
+    fn block(&self) -> Result<&Vec<Statement>, ParseError> {
+        return match &self.block {
+            Some(block) => Ok(block),
+            None => Err(self.error(format!("expected '{{' after '{}'", self.name.text))),
+        };
+    }
+
+    fn expect_arg_count(&self, count: usize) -> Result<(), ParseError> {
+        if self.args.len() != count {
+            return Err(self.error(format!(
+                "'{}' takes {} argument{}, found {}",
+                self.name.text,
+                count,
+                if count == 1 { "" } else { "s" },
+                self.args.len()
+            )));
+        }
+        return Ok(());
+    }
+
+    fn numbers(&self, count: usize) -> Result<Vec<RayTracingFloat>, ParseError> {
+        self.expect_arg_count(count)?;
+        return self.args.iter().map(parse_number).collect();
+    }
+
+    fn number(&self) -> Result<RayTracingFloat, ParseError> {
+        return Ok(self.numbers(1)?[0]);
+    }
+
+    fn vector(&self) -> Result<ray::Vector, ParseError> {
+        let v = self.numbers(3)?;
+        return Ok(ray::Vector::new(v[0], v[1], v[2]));
+    }
+
+    fn integer(&self) -> Result<usize, ParseError> {
+        self.expect_arg_count(1)?;
+        let arg = &self.args[0];
+        return arg.text.parse::<usize>().map_err(|_| {
+            arg.error(format!(
+                "expected a non-negative integer, found {}",
+                arg.describe()
+            ))
+        });
+    }
+
+    fn name_arg(&self) -> Result<&Token, ParseError> {
+        self.expect_arg_count(1)?;
+        return Ok(&self.args[0]);
+    }
+}
+
+fn parse_number(token: &Token) -> Result<RayTracingFloat, ParseError> {
+    return match token.text.parse::<RayTracingFloat>() {
+        Ok(value) if token.kind == TokenKind::Word && value.is_finite() => Ok(value),
+        _ => Err(token.error(format!("expected a number, found {}", token.describe()))),
+    };
+}
+
+// The properties set inside the block of a statement. Each property may be given at most once,
+// and every property has to be consumed by the interpreter, so that typos are reported.
+struct Properties<'a> {
+    owner: &'a Statement,
+    entries: Vec<&'a Statement>,
+}
+
+impl<'a> Properties<'a> {
+    fn new(owner: &'a Statement) -> Result<Self, ParseError> {
+        return Self::from_entries(owner, owner.block()?.iter().collect());
+    }
+
+    // For blocks that hold objects as well, like volumes. The statements with the given names are
+    // taken as properties, the rest are returned in order.
+    fn split(
+        owner: &'a Statement,
+        names: &[&str],
+    ) -> Result<(Self, Vec<&'a Statement>), ParseError> {
+        let (entries, rest) = owner
+            .block()?
+            .iter()
+            .partition(|s| names.contains(&s.name.text.as_str()));
+        return Ok((Self::from_entries(owner, entries)?, rest));
+    }
+
+    fn from_entries(owner: &'a Statement, all: Vec<&'a Statement>) -> Result<Self, ParseError> {
+        let mut entries: Vec<&'a Statement> = Vec::new();
+        for entry in all {
+            entry.expect_no_block()?;
+            if entries.iter().any(|e| e.name.text == entry.name.text) {
+                return Err(entry.error(format!(
+                    "property '{}' is set more than once",
+                    entry.name.text
+                )));
+            }
+            entries.push(entry);
+        }
+        return Ok(Self {
+            owner: owner,
+            entries: entries,
+        });
+    }
+
+    fn take(&mut self, name: &str) -> Option<&'a Statement> {
+        let index = self.entries.iter().position(|e| e.name.text == name)?;
+        return Some(self.entries.remove(index));
+    }
+
+    fn require(&mut self, name: &str) -> Result<&'a Statement, ParseError> {
+        let owner = self.owner;
+        return self.take(name).ok_or_else(|| {
+            owner.error(format!(
+                "missing property '{}' in '{}'",
+                name, owner.name.text
+            ))
+        });
+    }
+
+    fn finish(self) -> Result<(), ParseError> {
+        if let Some(entry) = self.entries.first() {
+            return Err(entry.error(format!(
+                "unknown property '{}' in '{}'",
+                entry.name.text, self.owner.name.text
+            )));
+        }
+        return Ok(());
+    }
+}
+
+//
+// Interpreter
+//
+struct Loader {
+    directory: std::path::PathBuf, // which relative file paths start from
+    textures: std::collections::HashMap<String, std::sync::Arc<dyn texture::Texture>>,
+    materials: std::collections::HashMap<String, std::sync::Arc<dyn material::Material>>,
+    time0: RayTracingFloat,
+    time1: RayTracingFloat,
+}
+
+impl Loader {
+    fn camera(&self, statement: &Statement) -> Result<camera::CameraSettings, ParseError> {
+        statement.expect_arg_count(0)?;
+        let mut props = Properties::new(statement)?;
+        let mut settings = camera::CameraSettings::default();
+        if let Some(p) = props.take("lookfrom") {
+            settings.lookfrom = p.vector()?;
+        }
+        if let Some(p) = props.take("lookat") {
+            settings.lookat = p.vector()?;
+        }
+        if let Some(p) = props.take("vup") {
+            settings.vup = p.vector()?;
+        }
+        if let Some(p) = props.take("vfov") {
+            settings.vfov = p.number()?;
+            if settings.vfov <= 0.0 || settings.vfov >= 180.0 {
+                return Err(p.error(String::from("vfov must be between 0 and 180 degrees")));
+            }
+        }
+        if let Some(p) = props.take("aperture") {
+            settings.aperture = p.number()?;
+            if settings.aperture < 0.0 {
+                return Err(p.error(String::from("aperture must not be negative")));
+            }
+        }
+        if let Some(p) = props.take("focus_dist") {
+            settings.focus_dist = p.number()?;
+            if settings.focus_dist <= 0.0 {
+                return Err(p.error(String::from("focus_dist must be positive")));
+            }
+        }
+        if let Some(p) = props.take("shutter") {
+            let times = p.numbers(2)?;
+            settings.time0 = times[0];
+            settings.time1 = times[1];
+        }
+        props.finish()?;
+        return Ok(settings);
+    }
+
+    fn image(&self, statement: &Statement, scene: &mut scenes::Scene) -> Result<(), ParseError> {
+        statement.expect_arg_count(0)?;
+        let mut props = Properties::new(statement)?;
+        if let Some(p) = props.take("aspect_ratio") {
+            scene.aspect_ratio = p.number()?;
+            if scene.aspect_ratio <= 0.0 {
+                return Err(p.error(String::from("aspect_ratio must be positive")));
+            }
+        }
+        if let Some(p) = props.take("width") {
+            scene.image_width = p.integer()?;
+            if scene.image_width == 0 {
+                return Err(p.error(String::from("width must be positive")));
+            }
+        }
+        if let Some(p) = props.take("samples_per_pixel") {
+            scene.samples_per_pixel = p.integer()?;
+            if scene.samples_per_pixel == 0 {
+                return Err(p.error(String::from("samples_per_pixel must be positive")));
+            }
+        }
+        if let Some(p) = props.take("max_depth") {
+            scene.max_depth = u32::try_from(p.integer()?)
+                .map_err(|_| p.error(format!("max_depth must be at most {}", u32::MAX)))?;
+        }
+        return props.finish();
+    }
+
+    fn environment(
+        &self,
+        kind: &Token,
+        statement: &Statement,
+    ) -> Result<std::sync::Arc<dyn environment::Environment>, ParseError> {
+        let mut props = Properties::new(statement)?;
+        let environment: std::sync::Arc<dyn environment::Environment> = match kind.text.as_str() {
+            "constant" => std::sync::Arc::new(environment::ConstantEnvironment::new(
+                props.require("color")?.vector()?,
+            )),
+            "gradient" => {
+                let sky = environment::GradientEnvironment::sky();
+                let bottom = match props.take("bottom") {
+                    Some(p) => p.vector()?,
+                    None => sky.bottom().clone(),
+                };
+                let top = match props.take("top") {
+                    Some(p) => p.vector()?,
+                    None => sky.top().clone(),
+                };
+                std::sync::Arc::new(environment::GradientEnvironment::new(bottom, top))
+            }
+            "daylight" => {
+                let defaults = environment::EnvironmentSettings::default();
+                let mut setting = |name: &str, default: RayTracingFloat| match props.take(name) {
+                    Some(p) => p.number(),
+                    None => Ok(default),
+                };
+                let sun_elevation = setting("sun_elevation", defaults.sun_elevation)?;
+                let sun_azimuth = setting("sun_azimuth", defaults.sun_azimuth)?;
+                let turbidity = setting("turbidity", defaults.turbidity)?;
+                let intensity = setting("intensity", defaults.intensity)?;
+                if !(0.0..=90.0).contains(&sun_elevation) {
+                    return Err(statement.error(String::from(
+                        "sun_elevation must be between 0 and 90 degrees",
+                    )));
+                }
+                if !(1.7..=10.0).contains(&turbidity) {
+                    return Err(
+                        statement.error(String::from("turbidity must be between 1.7 and 10"))
+                    );
+                }
+                std::sync::Arc::new(environment::DaylightEnvironment::new(
+                    sun_elevation,
+                    sun_azimuth,
+                    turbidity,
+                    intensity,
+                ))
+            }
+            "map" => {
+                let file = props.require("file")?;
+                let path = self.directory.join(&file.name_arg()?.text);
+                let rotation = match props.take("rotation") {
+                    Some(p) => p.number()?,
+                    None => 0.0,
+                };
+                let intensity = match props.take("intensity") {
+                    Some(p) => p.number()?,
+                    None => 1.0,
+                };
+                let map =
+                    environment::EnvironmentMap::load(&path.to_string_lossy(), rotation, intensity)
+                        .map_err(|err| file.error(err))?;
+                std::sync::Arc::new(map)
+            }
+            _ => {
+                return Err(kind.error(format!("unknown environment type {}", kind.describe())));
+            }
+        };
+        props.finish()?;
+        return Ok(environment);
+    }
+
+    // A texture valued property is either a color given as three numbers or the name of a
+    // previously declared texture.
+    fn texture_value(
+        &self,
+        statement: &Statement,
+    ) -> Result<std::sync::Arc<dyn texture::Texture>, ParseError> {
+        if statement.args.len() == 1 {
+            let name = &statement.args[0];
+            return match self.textures.get(&name.text) {
+                Some(texture) => Ok(texture.clone()),
+                None => Err(name.error(format!("unknown texture {}", name.describe()))),
+            };
+        }
+        return Ok(std::sync::Arc::new(texture::SolidColor::new(
+            statement.vector()?,
+        )));
+    }
+
+    fn texture(
+        &self,
+        kind: &Token,
+        statement: &Statement,
+    ) -> Result<std::sync::Arc<dyn texture::Texture>, ParseError> {
+        let mut props = Properties::new(statement)?;
+        let texture: std::sync::Arc<dyn texture::Texture> = match kind.text.as_str() {
+            "solid" => {
+                std::sync::Arc::new(texture::SolidColor::new(props.require("color")?.vector()?))
+            }
+            "checker" => std::sync::Arc::new(texture::CheckerTexture::new_with_textures(
+                self.texture_value(props.require("even")?)?,
+                self.texture_value(props.require("odd")?)?,
+            )),
+            _ => return Err(kind.error(format!("unknown texture type {}", kind.describe()))),
+        };
+        props.finish()?;
+        return Ok(texture);
+    }
+
+    fn material(
+        &self,
+        kind: &Token,
+        statement: &Statement,
+    ) -> Result<std::sync::Arc<dyn material::Material>, ParseError> {
+        let mut props = Properties::new(statement)?;
+        let material: std::sync::Arc<dyn material::Material> = match kind.text.as_str() {
+            "lambertian" => std::sync::Arc::new(material::Lambertian::new_with_texture(
+                self.texture_value(props.require("albedo")?)?,
+            )),
+            "metal" => {
+                let albedo = props.require("albedo")?.vector()?;
+                let fuzz = match props.take("fuzz") {
+                    Some(p) => p.number()?,
+                    None => 0.0,
+                };
+                std::sync::Arc::new(material::Metal::new(albedo, fuzz))
+            }
+            "dielectric" => {
+                std::sync::Arc::new(material::Dielectric::new(props.require("ir")?.number()?))
+            }
+            "diffuse_light" => std::sync::Arc::new(material::DiffuseLight::new_with_texture(
+                self.texture_value(props.require("emit")?)?,
+            )),
+            _ => return Err(kind.error(format!("unknown material type {}", kind.describe()))),
+        };
+        props.finish()?;
+        return Ok(material);
+    }
+
+    fn material_ref(
+        &self,
+        props: &mut Properties,
+    ) -> Result<std::sync::Arc<dyn material::Material>, ParseError> {
+        let name = props.require("material")?.name_arg()?;
+        return match self.materials.get(&name.text) {
+            Some(material) => Ok(material.clone()),
+            None => Err(name.error(format!("unknown material {}", name.describe()))),
+        };
+    }
+
+    // The statements nested inside a transform or group, gathered into a single object.
+    fn group(
+        &self,
+        statement: &Statement,
+    ) -> Result<Vec<std::sync::Arc<dyn hittable::Hittable>>, ParseError> {
+        let block = statement.block()?;
+        if block.is_empty() {
+            return Err(statement.error(format!(
+                "'{}' must contain at least one object",
+                statement.name.text
+            )));
+        }
+        return block.iter().map(|s| self.object(s)).collect();
+    }
+
+    fn group_object(
+        &self,
+        statement: &Statement,
+    ) -> Result<std::sync::Arc<dyn hittable::Hittable>, ParseError> {
+        let mut objects = self.group(statement)?;
+        if objects.len() == 1 {
+            return Ok(objects.pop().unwrap());
+        }
+        let mut list = hittable::HittableList::new_empty();
+        for object in objects {
+            list.add(object);
+        }
+        return Ok(std::sync::Arc::new(list));
+    }
+
+    // The density field of a heterogeneous volume, either read from a grid file or noise.
+    fn density_field(
+        &self,
+        statement: &Statement,
+        props: &mut Properties,
+    ) -> Result<std::sync::Arc<dyn density::DensityField>, ParseError> {
+        return match (props.take("grid"), props.take("noise")) {
+            (Some(grid), None) => {
+                let path = self.directory.join(&grid.name_arg()?.text);
+                let grid = density::DensityGrid::load(&path.to_string_lossy())
+                    .map_err(|err| grid.error(err))?;
+                Ok(std::sync::Arc::new(grid))
+            }
+            (None, Some(noise)) => {
+                let values = noise.numbers(2)?;
+                if values[0] <= 0.0 {
+                    return Err(noise.error(String::from("noise frequency must be positive")));
+                }
+                if values[1].fract() != 0.0 || !(1.0..=16.0).contains(&values[1]) {
+                    return Err(noise.error(String::from(
+                        "noise octaves must be a whole number from 1 to 16",
+                    )));
+                }
+                Ok(std::sync::Arc::new(density::NoiseDensity::new(
+                    values[0],
+                    values[1] as u32,
+                )))
+            }
+            (Some(_), Some(noise)) => Err(noise.error(format!(
+                "'{}' takes either 'grid' or 'noise', not both",
+                statement.name.text
+            ))),
+            (None, None) => Err(statement.error(format!(
+                "missing property 'grid' or 'noise' in '{}'",
+                statement.name.text
+            ))),
+        };
+    }
+
+    // The closed object a volume fills, exactly one.
+    fn boundary(
+        &self,
+        statement: &Statement,
+        objects: &[&Statement],
+    ) -> Result<std::sync::Arc<dyn hittable::Hittable>, ParseError> {
+        let mut boundary = objects
+            .iter()
+            .map(|s| self.object(s))
+            .collect::<Result<Vec<_>, _>>()?;
+        return match objects {
+            [_] => Ok(boundary.pop().unwrap()),
+            [] => Err(statement.error(format!(
+                "'{}' must contain a boundary object",
+                statement.name.text
+            ))),
+            [_, extra, ..] => Err(extra.error(format!(
+                "'{}' must contain a single boundary object",
+                statement.name.text
+            ))),
+        };
+    }
+
+    fn object(
+        &self,
+        statement: &Statement,
+    ) -> Result<std::sync::Arc<dyn hittable::Hittable>, ParseError> {
+        let object: std::sync::Arc<dyn hittable::Hittable> = match statement.name.text.as_str() {
+            "translate" => {
+                let offset = statement.vector()?;
+                std::sync::Arc::new(hittable::Translate::new(
+                    self.group_object(statement)?,
+                    offset,
+                ))
+            }
+            "rotate_y" => {
+                let angle = statement.number()?;
+                std::sync::Arc::new(hittable::Rotate_Y::new(
+                    self.group_object(statement)?,
+                    angle,
+                ))
+            }
+            "list" => {
+                statement.expect_arg_count(0)?;
+                let mut list = hittable::HittableList::new_empty();
+                for object in self.group(statement)? {
+                    list.add(object);
+                }
+                std::sync::Arc::new(list)
+            }
+            "constant_medium" => {
+                statement.expect_arg_count(0)?;
+                let (mut props, objects) = Properties::split(statement, &["density", "albedo"])?;
+                let density = props.require("density")?;
+                if density.number()? < 0.0 {
+                    return Err(density.error(String::from("density must not be negative")));
+                }
+                let medium = hittable_volume::ConstantMedium::new_with_texture(
+                    self.boundary(statement, &objects)?,
+                    density.number()?,
+                    self.texture_value(props.require("albedo")?)?,
+                );
+                props.finish()?;
+                std::sync::Arc::new(medium)
+            }
+            "bvh" => {
+                statement.expect_arg_count(0)?;
+                let objects = self.group(statement)?;
+                std::sync::Arc::new(hittable::BVH_Node::new(
+                    &objects,
+                    &0,
+                    &objects.len(),
+                    &self.time0,
+                    &self.time1,
+                ))
+            }
+            _ => {
+                statement.expect_arg_count(0)?;
+                let mut props = Properties::new(statement)?;
+                let object = self.primitive(statement, &mut props)?;
+                props.finish()?;
+                object
+            }
+        };
+        return Ok(object);
+    }
+
+    fn primitive(
+        &self,
+        statement: &Statement,
+        props: &mut Properties,
+    ) -> Result<std::sync::Arc<dyn hittable::Hittable>, ParseError> {
+        let object: std::sync::Arc<dyn hittable::Hittable> = match statement.name.text.as_str() {
+            "sphere" => std::sync::Arc::new(hittable_sphere::Sphere::new(
+                props.require("center")?.vector()?,
+                props.require("radius")?.number()?,
+                self.material_ref(props)?,
+            )),
+            "moving_sphere" => std::sync::Arc::new(hittable_sphere::MovingSphere::new(
+                props.require("center0")?.vector()?,
+                props.require("center1")?.vector()?,
+                props.require("time0")?.number()?,
+                props.require("time1")?.number()?,
+                props.require("radius")?.number()?,
+                self.material_ref(props)?,
+            )),
+            "xy_rect" => std::sync::Arc::new(hittable_box::XY_Rect::new(
+                props.require("x0")?.number()?,
+                props.require("x1")?.number()?,
+                props.require("y0")?.number()?,
+                props.require("y1")?.number()?,
+                props.require("k")?.number()?,
+                self.material_ref(props)?,
+            )),
+            "xz_rect" => std::sync::Arc::new(hittable_box::XZ_Rect::new(
+                props.require("x0")?.number()?,
+                props.require("x1")?.number()?,
+                props.require("z0")?.number()?,
+                props.require("z1")?.number()?,
+                props.require("k")?.number()?,
+                self.material_ref(props)?,
+            )),
+            "yz_rect" => std::sync::Arc::new(hittable_box::YZ_Rect::new(
+                props.require("y0")?.number()?,
+                props.require("y1")?.number()?,
+                props.require("z0")?.number()?,
+                props.require("z1")?.number()?,
+                props.require("k")?.number()?,
+                self.material_ref(props)?,
+            )),
+            "box" => std::sync::Arc::new(hittable_box::Box::new(
+                props.require("min")?.vector()?,
+                props.require("max")?.vector()?,
+                self.material_ref(props)?,
+            )),
+            "heterogeneous_medium" => {
+                let min = props.require("min")?.vector()?;
+                let max = props.require("max")?.vector()?;
+                let density = props.require("density")?;
+                if density.number()? < 0.0 {
+                    return Err(density.error(String::from("density must not be negative")));
+                }
+                let anisotropy = match props.take("anisotropy") {
+                    Some(p) if p.number()?.abs() >= 1.0 => {
+                        return Err(p.error(String::from(
+                            "anisotropy must be greater than -1 and less than 1",
+                        )));
+                    }
+                    Some(p) => p.number()?,
+                    None => 0.0,
+                };
+                std::sync::Arc::new(hittable_volume::HeterogeneousMedium::new(
+                    min,
+                    max,
+                    density.number()?,
+                    self.density_field(statement, props)?,
+                    self.texture_value(props.require("albedo")?)?,
+                    anisotropy,
+                ))
+            }
+            _ => {
+                return Err(
+                    statement.error(format!("unknown statement {}", statement.name.describe()))
+                );
+            }
+        };
+        return Ok(object);
+    }
+}
+
+// The settings a scene file gets when it does not specify them.
+fn default_scene() -> scenes::Scene {
+    return scenes::Scene {
+        world: hittable::HittableList::new_empty(),
+        environment: std::sync::Arc::new(environment::ConstantEnvironment::new(
+            color::Color::zero(),
+        )),
+        camera: camera::CameraSettings::default(),
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 100,
+        max_depth: 50,
+    };
+}
+
+// Parses a scene, looking up the files it refers to relative to the given directory.
+pub fn parse(source: &str, directory: &std::path::Path) -> Result<scenes::Scene, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let statements = parser.statements(false)?;
+
+    let mut scene = default_scene();
+    let mut loader = Loader {
+        directory: directory.to_path_buf(),
+        textures: std::collections::HashMap::new(),
+        materials: std::collections::HashMap::new(),
+        time0: scene.camera.time0,
+        time1: scene.camera.time1,
+    };
+
+    // The camera and image settings are applied first, so that the shutter times are known by
+    // the time any bounding volume hierarchy gets built.
+    let mut seen_camera = false;
+    let mut seen_image = false;
+    for statement in &statements {
+        let seen = match statement.name.text.as_str() {
+            "camera" => &mut seen_camera,
+            "image" => &mut seen_image,
+            _ => continue,
+        };
+        if *seen {
+            return Err(
+                statement.error(format!("'{}' is given more than once", statement.name.text))
+            );
+        }
+        *seen = true;
+
+        if statement.name.text == "camera" {
+            scene.camera = loader.camera(statement)?;
+            loader.time0 = scene.camera.time0;
+            loader.time1 = scene.camera.time1;
+        } else {
+            loader.image(statement, &mut scene)?;
+        }
+    }
+
+    let mut seen_environment = false;
+    for statement in &statements {
+        match statement.name.text.as_str() {
+            "camera" | "image" => {}
+            "background" | "environment" => {
+                if seen_environment {
+                    return Err(
+                        statement.error(String::from("the environment is given more than once"))
+                    );
+                }
+                seen_environment = true;
+                scene.environment = if statement.name.text == "background" {
+                    statement.expect_no_block()?;
+                    std::sync::Arc::new(environment::ConstantEnvironment::new(statement.vector()?))
+                } else {
+                    statement.expect_arg_count(1)?;
+                    loader.environment(&statement.args[0], statement)?
+                };
+            }
+            "texture" | "material" => {
+                statement.expect_arg_count(2)?;
+                let (name, kind) = (&statement.args[0], &statement.args[1]);
+                let is_texture = statement.name.text == "texture";
+                let defined = if is_texture {
+                    loader.textures.contains_key(&name.text)
+                } else {
+                    loader.materials.contains_key(&name.text)
+                };
+                if defined {
+                    return Err(name.error(format!(
+                        "{} {} is already defined",
+                        statement.name.text,
+                        name.describe()
+                    )));
+                }
+
+                if is_texture {
+                    let texture = loader.texture(kind, statement)?;
+                    loader.textures.insert(name.text.clone(), texture);
+                } else {
+                    let material = loader.material(kind, statement)?;
+                    loader.materials.insert(name.text.clone(), material);
+                }
+            }
+            _ => scene.world.add(loader.object(statement)?),
+        }
+    }
+
+    return Ok(scene);
+}
+
+pub fn load(path: &str) -> Result<scenes::Scene, String> {
+    let source = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let directory = std::path::Path::new(path)
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""));
+    return parse(&source, directory).map_err(|err| format!("{}:{}", path, err));
+}
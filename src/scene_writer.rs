@@ -0,0 +1,411 @@
+// Writes an in-memory scene out in the text format read by scene_file, so that e.g. randomly
+// generated scenes can be frozen, diffed and shared.
+use super::aabb;
+use super::density;
+use super::environment;
+use super::hittable;
+use super::hittable_box;
+use super::hittable_sphere;
+use super::hittable_volume;
+use super::material;
+use super::ray;
+use super::scenes;
+use super::texture;
+use super::utils::RayTracingFloat;
+
+const INDENT: &str = "    ";
+
+fn number(x: &RayTracingFloat) -> String {
+    // Display prints the shortest representation that parses back to the same value.
+    return format!("{}", x);
+}
+
+fn vector(v: &ray::Vector) -> String {
+    return format!("{} {} {}", number(&v.x()), number(&v.y()), number(&v.z()));
+}
+
+// Identifies shared objects by the address of their allocation.
+fn address<T: ?Sized>(object: &std::sync::Arc<T>) -> *const () {
+    return std::sync::Arc::as_ptr(object) as *const ();
+}
+
+struct Writer {
+    // Texture and material declarations, written ahead of the objects using them.
+    declarations: String,
+    textures: Vec<(*const (), String)>,
+    materials: Vec<(*const (), String)>,
+}
+
+impl Writer {
+    fn texture_value(
+        &mut self,
+        texture: &std::sync::Arc<dyn texture::Texture>,
+    ) -> Result<String, String> {
+        if let Some(solid) = texture.as_any().downcast_ref::<texture::SolidColor>() {
+            return Ok(vector(solid.color()));
+        }
+
+        if let Some((_, name)) = self.textures.iter().find(|(a, _)| *a == address(texture)) {
+            return Ok(name.clone());
+        }
+
+        let any = texture.as_any();
+        let (kind, properties) =
+            if let Some(checker) = any.downcast_ref::<texture::CheckerTexture>() {
+                (
+                    "checker",
+                    vec![
+                        format!("even {}", self.texture_value(checker.even())?),
+                        format!("odd {}", self.texture_value(checker.odd())?),
+                    ],
+                )
+            } else {
+                return Err(String::from("cannot write a texture of unknown type"));
+            };
+
+        let name = format!("{}_{}", kind, self.textures.len());
+        self.declarations += &format!(
+            "texture {} {} {{ {} }}\n",
+            name,
+            kind,
+            properties.join("; ")
+        );
+        self.textures.push((address(texture), name.clone()));
+        return Ok(name);
+    }
+
+    fn material_name(
+        &mut self,
+        material: &std::sync::Arc<dyn material::Material>,
+    ) -> Result<String, String> {
+        if let Some((_, name)) = self.materials.iter().find(|(a, _)| *a == address(material)) {
+            return Ok(name.clone());
+        }
+
+        let any = material.as_any();
+        let (kind, properties) = if let Some(m) = any.downcast_ref::<material::Lambertian>() {
+            (
+                "lambertian",
+                vec![format!("albedo {}", self.texture_value(m.albedo())?)],
+            )
+        } else if let Some(m) = any.downcast_ref::<material::Metal>() {
+            (
+                "metal",
+                vec![
+                    format!("albedo {}", vector(m.albedo())),
+                    format!("fuzz {}", number(m.fuzz())),
+                ],
+            )
+        } else if let Some(m) = any.downcast_ref::<material::Dielectric>() {
+            (
+                "dielectric",
+                vec![format!("ir {}", number(m.index_of_refraction()))],
+            )
+        } else if let Some(m) = any.downcast_ref::<material::DiffuseLight>() {
+            (
+                "diffuse_light",
+                vec![format!("emit {}", self.texture_value(m.emit())?)],
+            )
+        } else {
+            return Err(String::from("cannot write a material of unknown type"));
+        };
+
+        let name = format!("{}_{}", kind, self.materials.len());
+        self.declarations += &format!(
+            "material {} {} {{ {} }}\n",
+            name,
+            kind,
+            properties.join("; ")
+        );
+        self.materials.push((address(material), name.clone()));
+        return Ok(name);
+    }
+
+    // Collects the objects at the leaves of a bounding volume hierarchy.
+    fn bvh_leaves<'a>(
+        node: &'a hittable::BVH_Node,
+        leaves: &mut Vec<&'a std::sync::Arc<dyn hittable::Hittable>>,
+    ) {
+        let mut children = vec![node.left()];
+        // A node built from a single object holds it as both of its children.
+        if address(node.left()) != address(node.right()) {
+            children.push(node.right());
+        }
+
+        for child in children {
+            match child.as_any().downcast_ref::<hittable::BVH_Node>() {
+                Some(inner) => Self::bvh_leaves(inner, leaves),
+                None => leaves.push(child),
+            }
+        }
+    }
+
+    fn block(
+        &mut self,
+        header: String,
+        objects: &[&std::sync::Arc<dyn hittable::Hittable>],
+        indent: &str,
+        out: &mut String,
+    ) -> Result<(), String> {
+        *out += &format!("{}{} {{\n", indent, header);
+        let inner_indent = format!("{}{}", indent, INDENT);
+        for object in objects {
+            self.object(object, &inner_indent, out)?;
+        }
+        *out += &format!("{}}}\n", indent);
+        return Ok(());
+    }
+
+    // The objects a transform applies to. Lists are unwrapped, as a transform block with more
+    // than one object is read back as a list.
+    fn transformed_objects(
+        object: &std::sync::Arc<dyn hittable::Hittable>,
+    ) -> Vec<&std::sync::Arc<dyn hittable::Hittable>> {
+        return match object.as_any().downcast_ref::<hittable::HittableList>() {
+            Some(list) if !list.get_objects().is_empty() => list.get_objects().iter().collect(),
+            _ => vec![object],
+        };
+    }
+
+    fn object(
+        &mut self,
+        object: &std::sync::Arc<dyn hittable::Hittable>,
+        indent: &str,
+        out: &mut String,
+    ) -> Result<(), String> {
+        let any = object.as_any();
+
+        if let Some(list) = any.downcast_ref::<hittable::HittableList>() {
+            let objects = list.get_objects().iter().collect::<Vec<_>>();
+            return self.block(String::from("list"), &objects, indent, out);
+        }
+        if let Some(bvh) = any.downcast_ref::<hittable::BVH_Node>() {
+            let mut leaves = Vec::new();
+            Self::bvh_leaves(bvh, &mut leaves);
+            // The shape of the hierarchy depends on randomly chosen split axes, so write the
+            // leaves in an order that only depends on the objects themselves.
+            leaves.sort_by(|a, b| {
+                return aabb::box_x_compare(&***a, &***b)
+                    .then(aabb::box_y_compare(&***a, &***b))
+                    .then(aabb::box_z_compare(&***a, &***b));
+            });
+            return self.block(String::from("bvh"), &leaves, indent, out);
+        }
+        if let Some(translate) = any.downcast_ref::<hittable::Translate>() {
+            let objects = Self::transformed_objects(translate.object());
+            let header = format!("translate {}", vector(translate.offset()));
+            return self.block(header, &objects, indent, out);
+        }
+        if let Some(rotate) = any.downcast_ref::<hittable::Rotate_Y>() {
+            let objects = Self::transformed_objects(rotate.object());
+            let header = format!("rotate_y {}", number(rotate.angle()));
+            return self.block(header, &objects, indent, out);
+        }
+        if let Some(medium) = any.downcast_ref::<hittable_volume::ConstantMedium>() {
+            let inner_indent = format!("{}{}", indent, INDENT);
+            *out += &format!("{}constant_medium {{\n", indent);
+            *out += &format!("{}density {}\n", inner_indent, number(medium.density()));
+            *out += &format!(
+                "{}albedo {}\n",
+                inner_indent,
+                self.texture_value(medium.albedo())?
+            );
+            self.object(medium.boundary(), &inner_indent, out)?;
+            *out += &format!("{}}}\n", indent);
+            return Ok(());
+        }
+        if let Some(medium) = any.downcast_ref::<hittable_volume::HeterogeneousMedium>() {
+            let field = medium.field().as_any();
+            let field = if let Some(grid) = field.downcast_ref::<density::DensityGrid>() {
+                // Like environment maps, grids are referred to by their full path.
+                let path = std::fs::canonicalize(grid.path())
+                    .map_err(|err| format!("{}: {}", grid.path(), err))?;
+                format!("grid \"{}\"", path.to_string_lossy())
+            } else if let Some(noise) = field.downcast_ref::<density::NoiseDensity>() {
+                format!("noise {} {}", number(noise.frequency()), noise.octaves())
+            } else {
+                return Err(String::from("cannot write a density field of unknown type"));
+            };
+            *out += &format!(
+                "{}heterogeneous_medium {{ min {}; max {}; density {}; {}; albedo {}; anisotropy {} }}\n",
+                indent,
+                vector(medium.min()),
+                vector(medium.max()),
+                number(medium.density()),
+                field,
+                self.texture_value(medium.albedo())?,
+                number(medium.anisotropy())
+            );
+            return Ok(());
+        }
+
+        let (name, properties, material) =
+            if let Some(s) = any.downcast_ref::<hittable_sphere::Sphere>() {
+                (
+                    "sphere",
+                    vec![
+                        format!("center {}", vector(s.center())),
+                        format!("radius {}", number(s.radius())),
+                    ],
+                    s.material(),
+                )
+            } else if let Some(s) = any.downcast_ref::<hittable_sphere::MovingSphere>() {
+                (
+                    "moving_sphere",
+                    vec![
+                        format!("center0 {}", vector(s.center0())),
+                        format!("center1 {}", vector(s.center1())),
+                        format!("time0 {}", number(s.time0())),
+                        format!("time1 {}", number(s.time1())),
+                        format!("radius {}", number(s.radius())),
+                    ],
+                    s.material(),
+                )
+            } else if let Some(r) = any.downcast_ref::<hittable_box::XY_Rect>() {
+                (
+                    "xy_rect",
+                    vec![
+                        format!("x0 {}", number(r.x0())),
+                        format!("x1 {}", number(r.x1())),
+                        format!("y0 {}", number(r.y0())),
+                        format!("y1 {}", number(r.y1())),
+                        format!("k {}", number(r.k())),
+                    ],
+                    r.material(),
+                )
+            } else if let Some(r) = any.downcast_ref::<hittable_box::XZ_Rect>() {
+                (
+                    "xz_rect",
+                    vec![
+                        format!("x0 {}", number(r.x0())),
+                        format!("x1 {}", number(r.x1())),
+                        format!("z0 {}", number(r.z0())),
+                        format!("z1 {}", number(r.z1())),
+                        format!("k {}", number(r.k())),
+                    ],
+                    r.material(),
+                )
+            } else if let Some(r) = any.downcast_ref::<hittable_box::YZ_Rect>() {
+                (
+                    "yz_rect",
+                    vec![
+                        format!("y0 {}", number(r.y0())),
+                        format!("y1 {}", number(r.y1())),
+                        format!("z0 {}", number(r.z0())),
+                        format!("z1 {}", number(r.z1())),
+                        format!("k {}", number(r.k())),
+                    ],
+                    r.material(),
+                )
+            } else if let Some(b) = any.downcast_ref::<hittable_box::Box>() {
+                (
+                    "box",
+                    vec![
+                        format!("min {}", vector(b.min())),
+                        format!("max {}", vector(b.max())),
+                    ],
+                    b.material(),
+                )
+            } else {
+                return Err(String::from("cannot write an object of unknown type"));
+            };
+
+        let material_name = self.material_name(material)?;
+        *out += &format!(
+            "{}{} {{ {}; material {} }}\n",
+            indent,
+            name,
+            properties.join("; "),
+            material_name
+        );
+        return Ok(());
+    }
+}
+
+fn environment_statement(
+    environment: &std::sync::Arc<dyn environment::Environment>,
+) -> Result<String, String> {
+    let any = environment.as_any();
+    if let Some(constant) = any.downcast_ref::<environment::ConstantEnvironment>() {
+        return Ok(format!("background {}\n\n", vector(constant.color())));
+    }
+    if let Some(gradient) = any.downcast_ref::<environment::GradientEnvironment>() {
+        return Ok(format!(
+            "environment gradient {{ bottom {}; top {} }}\n\n",
+            vector(gradient.bottom()),
+            vector(gradient.top())
+        ));
+    }
+    if let Some(daylight) = any.downcast_ref::<environment::DaylightEnvironment>() {
+        return Ok(format!(
+            "environment daylight {{ sun_elevation {}; sun_azimuth {}; turbidity {}; intensity {} }}\n\n",
+            number(&daylight.sun_elevation()),
+            number(&daylight.sun_azimuth()),
+            number(&daylight.turbidity()),
+            number(&daylight.intensity())
+        ));
+    }
+    if let Some(map) = any.downcast_ref::<environment::EnvironmentMap>() {
+        // The scene may be written to another directory, so the map is referred to by its full
+        // path.
+        let path =
+            std::fs::canonicalize(map.path()).map_err(|err| format!("{}: {}", map.path(), err))?;
+        return Ok(format!(
+            "environment map {{ file \"{}\"; rotation {}; intensity {} }}\n\n",
+            path.to_string_lossy(),
+            number(&map.rotation()),
+            number(&map.intensity())
+        ));
+    }
+    return Err(String::from("cannot write an environment of unknown type"));
+}
+
+pub fn write(scene: &scenes::Scene) -> Result<String, String> {
+    let camera = &scene.camera;
+    let mut text = String::new();
+    text += "camera {\n";
+    text += &format!("{}lookfrom {}\n", INDENT, vector(&camera.lookfrom));
+    text += &format!("{}lookat {}\n", INDENT, vector(&camera.lookat));
+    text += &format!("{}vup {}\n", INDENT, vector(&camera.vup));
+    text += &format!("{}vfov {}\n", INDENT, number(&camera.vfov));
+    text += &format!("{}aperture {}\n", INDENT, number(&camera.aperture));
+    text += &format!("{}focus_dist {}\n", INDENT, number(&camera.focus_dist));
+    text += &format!(
+        "{}shutter {} {}\n",
+        INDENT,
+        number(&camera.time0),
+        number(&camera.time1)
+    );
+    text += "}\n\n";
+
+    text += "image {\n";
+    text += &format!("{}aspect_ratio {}\n", INDENT, number(&scene.aspect_ratio));
+    text += &format!("{}width {}\n", INDENT, scene.image_width);
+    text += &format!("{}samples_per_pixel {}\n", INDENT, scene.samples_per_pixel);
+    text += &format!("{}max_depth {}\n", INDENT, scene.max_depth);
+    text += "}\n\n";
+
+    text += &environment_statement(&scene.environment)?;
+
+    let mut writer = Writer {
+        declarations: String::new(),
+        textures: Vec::new(),
+        materials: Vec::new(),
+    };
+    let mut objects = String::new();
+    for object in scene.world.get_objects() {
+        writer.object(object, "", &mut objects)?;
+    }
+
+    if !writer.declarations.is_empty() {
+        text += &writer.declarations;
+        text += "\n";
+    }
+    text += &objects;
+    return Ok(text);
+}
+
+pub fn save(path: &str, scene: &scenes::Scene) -> Result<(), String> {
+    let text = write(scene)?;
+    return std::fs::write(path, text).map_err(|err| format!("{}: {}", path, err));
+}
@@ -0,0 +1,381 @@
+use super::camera;
+use super::color;
+use super::environment;
+use super::hittable;
+use super::hittable_box;
+use super::hittable_sphere;
+use super::hittable_volume;
+use super::material;
+use super::ray;
+use super::texture;
+use super::utils;
+use super::utils::RayTracingFloat;
+
+// A world together with its environment and the camera and image settings it is meant to be
+// rendered with. Every setting can still be overridden from the command line.
+pub struct Scene {
+    pub world: hittable::HittableList,
+    pub environment: std::sync::Arc<dyn environment::Environment>,
+    pub camera: camera::CameraSettings,
+    pub aspect_ratio: RayTracingFloat,
+    pub image_width: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: u32,
+}
+
+pub struct BuiltinScene {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub build: fn() -> Scene,
+}
+
+pub const BUILTIN_SCENES: &[BuiltinScene] = &[
+    BuiltinScene {
+        name: "random_scene",
+        description: "final scene of \"Ray Tracing in One Weekend\" with moving spheres",
+        build: random_scene,
+    },
+    BuiltinScene {
+        name: "cornell_box",
+        description: "Cornell box from \"Ray Tracing: The Next Week\"",
+        build: cornell_box,
+    },
+    BuiltinScene {
+        name: "cornell_smoke",
+        description: "Cornell box with blocks of smoke and fog",
+        build: cornell_smoke,
+    },
+];
+
+pub const DEFAULT_SCENE: &str = "cornell_box";
+
+pub fn find_builtin(name: &str) -> Option<&'static BuiltinScene> {
+    return BUILTIN_SCENES.iter().find(|scene| scene.name == name);
+}
+
+//
+// Random Scene
+//
+fn random_scene() -> Scene {
+    // settings taken from "Ray Tracing in One Weekend"
+    return Scene {
+        world: random_scene_world(),
+        environment: std::sync::Arc::new(environment::ConstantEnvironment::new(color::Color::new(
+            0.70, 0.80, 1.00,
+        ))),
+        camera: camera::CameraSettings {
+            lookfrom: ray::Point::new(13.0, 2.0, 3.0),
+            lookat: ray::Point::new(0.0, 0.0, 0.0),
+            vfov: 20.0,
+            aperture: 0.1,
+            ..camera::CameraSettings::default()
+        },
+        aspect_ratio: 3.0 / 2.0,
+        image_width: 1200,
+        samples_per_pixel: 500,
+        max_depth: 50,
+    };
+}
+
+fn random_scene_world() -> hittable::HittableList {
+    let mut objects = hittable::HittableList::new_empty();
+
+    let checker = std::sync::Arc::new(texture::CheckerTexture::new(
+        color::Color::new(0.2, 0.3, 0.1),
+        color::Color::new(0.9, 0.9, 0.9),
+    ));
+    let ground_material = std::sync::Arc::new(material::Lambertian::new_with_texture(checker));
+    objects.add(std::sync::Arc::new(hittable_sphere::Sphere::new(
+        ray::Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground_material,
+    )));
+
+    let ref_point = ray::Point::new(4.0, 0.2, 0.0);
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = utils::random_double(&0.0, &1.0);
+            let center = ray::Point::new(
+                a as RayTracingFloat + 0.9 * utils::random_double(&0.0, &1.0),
+                0.2,
+                b as RayTracingFloat + 0.9 * utils::random_double(&0.0, &1.0),
+            );
+
+            if (&center - &ref_point).length() > 0.9 {
+                if choose_mat < 0.8 {
+                    // diffuse
+                    let albedo =
+                        color::Color::random(&0.0, &1.0) * color::Color::random(&0.0, &1.0);
+                    let sphere_material = std::sync::Arc::new(material::Lambertian::new(albedo));
+                    let center2 =
+                        &center + ray::Vector::new(0.0, utils::random_double(&0.0, &0.5), 0.0);
+                    objects.add(std::sync::Arc::new(hittable_sphere::MovingSphere::new(
+                        center,
+                        center2,
+                        0.0,
+                        1.0,
+                        0.2,
+                        sphere_material,
+                    )));
+                } else if choose_mat < 0.95 {
+                    // metal
+                    let albedo = color::Color::random(&0.5, &1.0);
+                    let fuzz = utils::random_double(&0.0, &0.5);
+                    let sphere_material = std::sync::Arc::new(material::Metal::new(albedo, fuzz));
+                    objects.add(std::sync::Arc::new(hittable_sphere::Sphere::new(
+                        center,
+                        0.2,
+                        sphere_material,
+                    )));
+                } else {
+                    // glass
+                    let sphere_material = std::sync::Arc::new(material::Dielectric::new(1.5));
+                    objects.add(std::sync::Arc::new(hittable_sphere::Sphere::new(
+                        center,
+                        0.2,
+                        sphere_material,
+                    )));
+                }
+            }
+        }
+    }
+
+    let material1 = std::sync::Arc::new(material::Dielectric::new(1.5));
+    objects.add(std::sync::Arc::new(hittable_sphere::Sphere::new(
+        ray::Point::new(0.0, 1.0, 0.0),
+        1.0,
+        material1,
+    )));
+
+    let material2 =
+        std::sync::Arc::new(material::Lambertian::new(color::Color::new(0.4, 0.2, 0.1)));
+    objects.add(std::sync::Arc::new(hittable_sphere::Sphere::new(
+        ray::Point::new(-4.0, 1.0, 0.0),
+        1.0,
+        material2,
+    )));
+
+    let material3 =
+        std::sync::Arc::new(material::Metal::new(color::Color::new(0.7, 0.6, 0.5), 0.0));
+    objects.add(std::sync::Arc::new(hittable_sphere::Sphere::new(
+        ray::Point::new(4.0, 1.0, 0.0),
+        1.0,
+        material3,
+    )));
+
+    // return objects;
+    return hittable::HittableList::new(std::sync::Arc::new(
+        hittable::BVH_Node::new_from_hittable_list(objects, &0.0, &1.0),
+    ));
+}
+
+//
+// Cornell Box
+//
+fn cornell_box() -> Scene {
+    // settings taken from "Ray Tracing: The Next Week"
+    return Scene {
+        world: cornell_box_world(),
+        environment: std::sync::Arc::new(environment::ConstantEnvironment::new(
+            color::Color::zero(),
+        )),
+        camera: camera::CameraSettings {
+            lookfrom: ray::Point::new(278.0, 278.0, -800.0),
+            lookat: ray::Point::new(278.0, 278.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.0,
+            ..camera::CameraSettings::default()
+        },
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        max_depth: 50,
+    };
+}
+
+fn cornell_box_world() -> hittable::HittableList {
+    let red = std::sync::Arc::new(material::Lambertian::new(color::Color::new(
+        0.65, 0.05, 0.05,
+    )));
+    let white = std::sync::Arc::new(material::Lambertian::new(color::Color::new(
+        0.73, 0.73, 0.73,
+    )));
+    let green = std::sync::Arc::new(material::Lambertian::new(color::Color::new(
+        0.12, 0.45, 0.15,
+    )));
+    let light = std::sync::Arc::new(material::DiffuseLight::new(color::Color::new(
+        15.0, 15.0, 15.0,
+    )));
+
+    let mut objects = hittable::HittableList::new_empty();
+
+    objects.add(std::sync::Arc::new(hittable_box::YZ_Rect::new(
+        0.0, 555.0, 0.0, 555.0, 555.0, green,
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::YZ_Rect::new(
+        0.0, 555.0, 0.0, 555.0, 0.0, red,
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XZ_Rect::new(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XZ_Rect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XZ_Rect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XY_Rect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+
+    let mut box1 = std::sync::Arc::new(hittable_box::Box::new(
+        ray::Point::new(0.0, 0.0, 0.0),
+        ray::Point::new(165.0, 330.0, 165.0),
+        white.clone(),
+    )) as std::sync::Arc<dyn hittable::Hittable>;
+    box1 = std::sync::Arc::new(hittable::Rotate_Y::new(box1, 15.0));
+    box1 = std::sync::Arc::new(hittable::Translate::new(
+        box1,
+        ray::Vector::new(265.0, 0.0, 295.0),
+    ));
+    objects.add(box1);
+
+    let mut box2 = std::sync::Arc::new(hittable_box::Box::new(
+        ray::Point::new(0.0, 0.0, 0.0),
+        ray::Point::new(165.0, 165.0, 165.0),
+        white,
+    )) as std::sync::Arc<dyn hittable::Hittable>;
+    box2 = std::sync::Arc::new(hittable::Rotate_Y::new(box2, -18.0));
+    box2 = std::sync::Arc::new(hittable::Translate::new(
+        box2,
+        ray::Vector::new(130.0, 0.0, 65.0),
+    ));
+    objects.add(box2);
+
+    return objects;
+}
+
+//
+// Cornell Smoke
+//
+fn cornell_smoke() -> Scene {
+    // settings taken from "Ray Tracing: The Next Week"
+    return Scene {
+        world: cornell_smoke_world(),
+        environment: std::sync::Arc::new(environment::ConstantEnvironment::new(
+            color::Color::zero(),
+        )),
+        camera: camera::CameraSettings {
+            lookfrom: ray::Point::new(278.0, 278.0, -800.0),
+            lookat: ray::Point::new(278.0, 278.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.0,
+            ..camera::CameraSettings::default()
+        },
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        max_depth: 50,
+    };
+}
+
+fn cornell_smoke_world() -> hittable::HittableList {
+    let red = std::sync::Arc::new(material::Lambertian::new(color::Color::new(
+        0.65, 0.05, 0.05,
+    )));
+    let white = std::sync::Arc::new(material::Lambertian::new(color::Color::new(
+        0.73, 0.73, 0.73,
+    )));
+    let green = std::sync::Arc::new(material::Lambertian::new(color::Color::new(
+        0.12, 0.45, 0.15,
+    )));
+    let light = std::sync::Arc::new(material::DiffuseLight::new(color::Color::new(
+        7.0, 7.0, 7.0,
+    )));
+
+    let mut objects = hittable::HittableList::new_empty();
+
+    objects.add(std::sync::Arc::new(hittable_box::YZ_Rect::new(
+        0.0, 555.0, 0.0, 555.0, 555.0, green,
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::YZ_Rect::new(
+        0.0, 555.0, 0.0, 555.0, 0.0, red,
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XZ_Rect::new(
+        113.0, 443.0, 127.0, 432.0, 554.0, light,
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XZ_Rect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XZ_Rect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    )));
+    objects.add(std::sync::Arc::new(hittable_box::XY_Rect::new(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    )));
+
+    let mut box1 = std::sync::Arc::new(hittable_box::Box::new(
+        ray::Point::new(0.0, 0.0, 0.0),
+        ray::Point::new(165.0, 330.0, 165.0),
+        white.clone(),
+    )) as std::sync::Arc<dyn hittable::Hittable>;
+    box1 = std::sync::Arc::new(hittable::Rotate_Y::new(box1, 15.0));
+    box1 = std::sync::Arc::new(hittable::Translate::new(
+        box1,
+        ray::Vector::new(265.0, 0.0, 295.0),
+    ));
+
+    let mut box2 = std::sync::Arc::new(hittable_box::Box::new(
+        ray::Point::new(0.0, 0.0, 0.0),
+        ray::Point::new(165.0, 165.0, 165.0),
+        white,
+    )) as std::sync::Arc<dyn hittable::Hittable>;
+    box2 = std::sync::Arc::new(hittable::Rotate_Y::new(box2, -18.0));
+    box2 = std::sync::Arc::new(hittable::Translate::new(
+        box2,
+        ray::Vector::new(130.0, 0.0, 65.0),
+    ));
+
+    objects.add(std::sync::Arc::new(hittable_volume::ConstantMedium::new(
+        box1,
+        0.01,
+        color::Color::new(0.0, 0.0, 0.0),
+    )));
+    objects.add(std::sync::Arc::new(hittable_volume::ConstantMedium::new(
+        box2,
+        0.01,
+        color::Color::new(1.0, 1.0, 1.0),
+    )));
+
+    return objects;
+}
@@ -2,8 +2,11 @@ use super::color;
 use super::ray;
 use super::utils::RayTracingFloat;
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: &RayTracingFloat, v: &RayTracingFloat, p: &ray::Point) -> &color::Color;
+
+    // Gives access to the concrete type, e.g. for writing the scene graph out to a file.
+    fn as_any(&self) -> &dyn std::any::Any;
 }
 
 //
@@ -17,29 +20,55 @@ impl SolidColor {
     pub fn new(color: color::Color) -> Self {
         return Self { color_value: color };
     }
+
+    pub fn color(&self) -> &color::Color {
+        return &self.color_value;
+    }
 }
 
 impl Texture for SolidColor {
     fn value(&self, _u: &RayTracingFloat, _v: &RayTracingFloat, _p: &ray::Point) -> &color::Color {
         return &self.color_value;
     }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
 
 //
 // Checker Texture
 //
 pub struct CheckerTexture {
-    odd: std::rc::Rc<dyn Texture>,
-    even: std::rc::Rc<dyn Texture>,
+    odd: std::sync::Arc<dyn Texture>,
+    even: std::sync::Arc<dyn Texture>,
 }
 
 impl CheckerTexture {
     pub fn new(c1: color::Color, c2: color::Color) -> Self {
         return Self {
-            even: std::rc::Rc::new(SolidColor::new(c1)),
-            odd: std::rc::Rc::new(SolidColor::new(c2)),
+            even: std::sync::Arc::new(SolidColor::new(c1)),
+            odd: std::sync::Arc::new(SolidColor::new(c2)),
+        };
+    }
+
+    pub fn new_with_textures(
+        even: std::sync::Arc<dyn Texture>,
+        odd: std::sync::Arc<dyn Texture>,
+    ) -> Self {
+        return Self {
+            even: even,
+            odd: odd,
         };
     }
+
+    pub fn even(&self) -> &std::sync::Arc<dyn Texture> {
+        return &self.even;
+    }
+
+    pub fn odd(&self) -> &std::sync::Arc<dyn Texture> {
+        return &self.odd;
+    }
 }
 
 impl Texture for CheckerTexture {
@@ -51,4 +80,8 @@ impl Texture for CheckerTexture {
             return self.even.value(u, v, p);
         }
     }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        return self;
+    }
 }
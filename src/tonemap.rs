@@ -0,0 +1,177 @@
+// Tone mapping compresses the unbounded linear radiance of a render into the [0, 1] range of a
+// display. The curves are applied to each color channel separately.
+use super::color;
+use super::utils::RayTracingFloat;
+
+pub trait ToneMapper: Send + Sync {
+    fn map(&self, x: &RayTracingFloat) -> RayTracingFloat;
+}
+
+//
+// Clamp
+//
+// Linear, anything brighter than 1 is clipped.
+pub struct Clamp {}
+
+impl ToneMapper for Clamp {
+    fn map(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return x.clamp(0.0, 1.0);
+    }
+}
+
+//
+// Reinhard
+//
+pub struct Reinhard {}
+
+impl ToneMapper for Reinhard {
+    fn map(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return x / (1.0 + x);
+    }
+}
+
+//
+// Extended Reinhard
+//
+// Reinhard curve rescaled so that the white point maps to 1 instead of only approaching it.
+pub struct ExtendedReinhard {
+    white: RayTracingFloat,
+}
+
+impl ExtendedReinhard {
+    pub fn new(white: RayTracingFloat) -> Self {
+        return Self { white: white };
+    }
+}
+
+impl ToneMapper for ExtendedReinhard {
+    fn map(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        return x * (1.0 + x / (self.white * self.white)) / (1.0 + x);
+    }
+}
+
+//
+// ACES Filmic
+//
+// Krzysztof Narkowicz's fit of the ACES reference rendering and output transforms.
+pub struct Aces {}
+
+impl ToneMapper for Aces {
+    fn map(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        let a = 2.51;
+        let b = 0.03;
+        let c = 2.43;
+        let d = 0.59;
+        let e = 0.14;
+        return (x * (a * x + b)) / (x * (c * x + d) + e);
+    }
+}
+
+//
+// Hable
+//
+// John Hable's filmic curve from Uncharted 2, normalized so that the white point maps to 1.
+pub struct Hable {
+    white: RayTracingFloat,
+}
+
+impl Hable {
+    pub fn new(white: RayTracingFloat) -> Self {
+        return Self { white: white };
+    }
+
+    fn curve(x: &RayTracingFloat) -> RayTracingFloat {
+        let a = 0.15; // shoulder strength
+        let b = 0.50; // linear strength
+        let c = 0.10; // linear angle
+        let d = 0.20; // toe strength
+        let e = 0.02; // toe numerator
+        let f = 0.30; // toe denominator
+        return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+    }
+}
+
+impl ToneMapper for Hable {
+    fn map(&self, x: &RayTracingFloat) -> RayTracingFloat {
+        // The exposure bias of 2 from the original presentation.
+        return Self::curve(&(2.0 * x)) / Self::curve(&self.white);
+    }
+}
+
+//
+// Operators
+//
+pub const DEFAULT_WHITE_POINT: RayTracingFloat = 4.0;
+pub const DEFAULT_HABLE_WHITE_POINT: RayTracingFloat = 11.2;
+
+pub struct Operator {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub build: fn(&Option<RayTracingFloat>) -> std::sync::Arc<dyn ToneMapper>,
+}
+
+pub const OPERATORS: &[Operator] = &[
+    Operator {
+        name: "clamp",
+        description: "linear, clipping everything brighter than white",
+        build: |_white| std::sync::Arc::new(Clamp {}),
+    },
+    Operator {
+        name: "reinhard",
+        description: "x / (1 + x)",
+        build: |_white| std::sync::Arc::new(Reinhard {}),
+    },
+    Operator {
+        name: "reinhard-extended",
+        description: "Reinhard curve, white at the white point [default: 4]",
+        build: |white| {
+            std::sync::Arc::new(ExtendedReinhard::new(white.unwrap_or(DEFAULT_WHITE_POINT)))
+        },
+    },
+    Operator {
+        name: "aces",
+        description: "ACES filmic curve",
+        build: |_white| std::sync::Arc::new(Aces {}),
+    },
+    Operator {
+        name: "hable",
+        description: "Uncharted 2 filmic curve, white at the white point [default: 11.2]",
+        build: |white| std::sync::Arc::new(Hable::new(white.unwrap_or(DEFAULT_HABLE_WHITE_POINT))),
+    },
+];
+
+pub const DEFAULT_OPERATOR: &str = "clamp";
+
+pub fn find_operator(name: &str) -> Option<&'static Operator> {
+    return OPERATORS.iter().find(|operator| operator.name == name);
+}
+
+//
+// Tone Mapping
+//
+// Everything needed to turn linear radiance into display values.
+pub struct ToneMapping {
+    pub exposure: RayTracingFloat, // in stops (EV), every stop doubles the brightness
+    pub operator: std::sync::Arc<dyn ToneMapper>,
+}
+
+impl ToneMapping {
+    pub fn new(exposure: RayTracingFloat, operator: std::sync::Arc<dyn ToneMapper>) -> Self {
+        return Self {
+            exposure: exposure,
+            operator: operator,
+        };
+    }
+
+    // Maps a linear color to [0, 1], before the display encoding.
+    pub fn apply(&self, c: &color::Color) -> color::Color {
+        let scale = (2.0 as RayTracingFloat).powf(self.exposure);
+        let mut mapped = color::Color::zero();
+        for i in 0..3 {
+            // Negative values and NaNs, e.g. from a bad sample, are shown as black.
+            let x = (c[i] * scale).max(0.0);
+            mapped[i] = self.operator.map(&x).clamp(0.0, 1.0);
+        }
+        return mapped;
+    }
+}
@@ -1,4 +1,5 @@
 use rand::Rng;
+use rand::SeedableRng;
 
 pub type RayTracingFloat = f64;
 
@@ -7,12 +8,19 @@ pub fn random_int(min: &i32, max: &i32) -> i32 {
     return random_double(&(*min as RayTracingFloat), &((max + 1) as RayTracingFloat)) as i32;
 }
 
+thread_local!(static RNG_STORAGE : std::cell::RefCell<rand::rngs::StdRng>
+    = std::cell::RefCell::new(rand::rngs::StdRng::from_entropy()));
+
 pub fn random_double(min: &RayTracingFloat, max: &RayTracingFloat) -> RayTracingFloat {
-    thread_local!(static RNG_STORAGE : std::cell::RefCell<rand::prelude::ThreadRng> 
-        = std::cell::RefCell::new(rand::thread_rng()));
     return RNG_STORAGE.with(|rng_ref| return rng_ref.borrow_mut().gen_range(*min..*max));
 }
 
+// Restarts the random numbers of the calling thread from the given seed, making everything drawn
+// from them afterwards, like the objects of a randomly generated scene, reproducible.
+pub fn seed_random(seed: &u64) {
+    RNG_STORAGE.with(|rng_ref| *rng_ref.borrow_mut() = rand::rngs::StdRng::seed_from_u64(*seed));
+}
+
 #[inline]
 pub fn clamp<T: PartialOrd>(input: T, min: T, max: T) -> T {
     debug_assert!(min <= max, "min must be less than or equal to max");